@@ -1,13 +1,11 @@
-use indexmap::IndexMap as Map;
-use once_cell::sync::Lazy;
+use module_parser::{inspect, parse, Error, IdFormat, ParseResult};
 use rayon::prelude::*;
 use regex::Regex;
-use serde::Serialize;
 use std::{
-    env, fmt,
+    env,
     fs::{self, File},
-    io::{self, BufRead, BufReader, BufWriter, Write},
-    path::PathBuf,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
 
@@ -15,142 +13,144 @@ use walkdir::WalkDir;
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
-
-#[derive(Serialize)]
-struct ParseResult {
-    module_name: Option<String>,
-    #[serde(rename = "npcs")]
-    var_to_id_map: Map<String, i64>,
-    #[serde(skip)]
-    missing_vars: Vec<(String, String)>,
-    #[serde(skip)]
-    missing_ids: Vec<(i64, String)>,
-}
+/// What became of a file, errors come with its path.
+type FileResult = Result<(PathBuf, ParseResult), (PathBuf, Error)>;
 
-impl fmt::Debug for ParseResult {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("ParseResult")
-            .field("Variable-to-ID map", &self.var_to_id_map)
-            .field("Missing variables", &self.missing_vars)
-            .field("Missing IDs", &self.missing_ids)
-            .finish()
-    }
+/// What is printed instead of writing files.
+#[derive(Clone, Copy)]
+enum Report {
+    /// Everything that was parsed, a JSON object per file.
+    Inspect,
+    /// Only the number of modules and IDs and how many of them were matched.
+    Stats { per_module: bool },
 }
 
-#[derive(Copy, Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
-enum ParseState {
-    ParsingIds,
-    ParsingVars,
-    Neither,
-}
+/// Files that are parsed by default, each directory's trash module.
+const DEFAULT_FILES: &str = "Trash.lua";
 
-fn parse(mut input: BufReader<File>) -> Result<ParseResult, io::Error> {
-    static IDS_START: &str = "mod:RegisterEnableMob(";
-    static VARS_START: &str = "if L then";
-
-    static ID_REGEX: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r#"^\s*(\d+),?\s*--\s*(.+?)\n$"#).unwrap());
-    static VAR_REGEX: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r#"^\s*L\.(\w+)\s*=\s*"(.+?)""#).unwrap());
-    static MODULE_DECL_REGEX: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r#"^\s*local\s*mod(?:,\s*CL)?\s*=\s*BigWigs:NewBoss\("(.*?)""#).unwrap()
-    });
-
-    let mut ids_map = Map::with_capacity(16);
-    let mut vars_map = Map::with_capacity(16);
-    let mut module_name = None;
-
-    let mut state = ParseState::Neither;
-    let mut parsed_blocks = 0;
-
-    let mut line = String::new();
-    while input.read_line(&mut line)? > 0 {
-        match state {
-            ParseState::ParsingIds => match ID_REGEX.captures(&line) {
-                Some(caps) => {
-                    ids_map.insert(
-                        caps.get(2).unwrap().as_str().trim().to_string(),
-                        caps.get(1)
-                            .map(|cap| cap.as_str())
-                            .and_then(|cap| cap.parse::<i64>().ok())
-                            .unwrap(),
-                    );
-                }
-                None => {
-                    if line.find(')').is_some() {
-                        state = ParseState::Neither;
-                        if parsed_blocks == 2 {
-                            break;
-                        }
-                        parsed_blocks += 1;
-                    }
-                }
-            },
-            ParseState::ParsingVars => match VAR_REGEX.captures(&line) {
-                Some(caps) => {
-                    vars_map.insert(
-                        caps.get(2).unwrap().as_str().to_string(),
-                        caps.get(1).unwrap().as_str().to_string(),
-                    );
+/// Turns a glob (`*.lua`, `Trash?.lua`, `[A-Z]*.lua`) into a regex that matches whole file names.
+fn glob_regex(pattern: &str) -> Result<Regex, Error> {
+    if pattern.contains(['/', '\\']) {
+        return Err(format!(
+            "\"{pattern}\" has a path separator in it, only file names are matched"
+        )
+        .into());
+    }
+
+    let mut source = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => source.push_str(".*"),
+            '?' => source.push('.'),
+            '[' => {
+                source.push('[');
+                if chars.as_str().starts_with('!') {
+                    chars.next();
+                    source.push('^');
                 }
-                None => {
-                    if line.trim() == "end" {
-                        state = ParseState::Neither;
-                        if parsed_blocks == 2 {
-                            break;
-                        }
-                        parsed_blocks += 1;
+                // A `]` right after the opening bracket is a part of the class.
+                let mut first = true;
+                loop {
+                    match chars.next() {
+                        Some(']') if !first => break,
+                        Some('-') => source.push('-'),
+                        Some(c) => source.push_str(&regex::escape(&c.to_string())),
+                        None => return Err(format!("\"{pattern}\" has an unclosed [").into()),
                     }
+                    first = false;
                 }
-            },
-            ParseState::Neither => {
-                if line.starts_with(IDS_START) {
-                    state = ParseState::ParsingIds;
-                } else if line.starts_with(VARS_START) {
-                    state = ParseState::ParsingVars;
-                } else if let Some(caps) = MODULE_DECL_REGEX.captures(&line) {
-                    module_name = caps.get(1).map(|v| v.as_str().into());
-                    parsed_blocks += 1;
-                }
+                source.push(']');
             }
+            c => source.push_str(&regex::escape(&c.to_string())),
         }
-        line.clear();
     }
+    source.push('$');
 
-    let mut var_to_id_map = Map::with_capacity(vars_map.len());
-    let mut missing_vars = Vec::with_capacity(4);
+    Regex::new(&source).map_err(|e| format!("\"{pattern}\" isn't a valid pattern: {e}").into())
+}
 
-    for (value, variable) in vars_map.into_iter() {
-        if let Some(id) = ids_map.remove(&value) {
-            var_to_id_map.insert(variable, id);
-        } else {
-            missing_vars.push((variable, value));
-        }
-    }
+/// Where the output of `relative_path` (relative to the input directory) goes: trash modules
+/// are named after their directory, other files after themselves.
+fn output_path(output_dir: &Path, relative_path: &Path, extension: &str) -> PathBuf {
+    let named_after_dir = relative_path.ends_with(DEFAULT_FILES)
+        && relative_path
+            .parent()
+            .is_some_and(|parent| !parent.as_os_str().is_empty());
+    let path = if named_after_dir {
+        relative_path.parent().unwrap()
+    } else {
+        relative_path
+    };
 
-    let missing_ids: Vec<_> = ids_map
-        .into_iter()
-        .map(|(comment, id)| (id, comment))
-        .collect();
+    output_dir.join(path).with_extension(extension)
+}
+
+fn write_to_file(
+    parse_result: &ParseResult,
+    by_id: Option<IdFormat>,
+    mut output: BufWriter<File>,
+) -> Result<(), Error> {
+    match by_id {
+        Some(format) => output.write_all(format.render(parse_result)?.as_bytes())?,
+        None => serde_yaml::to_writer(&mut output, parse_result)?,
+    }
+    output.flush()?;
 
-    Ok(ParseResult {
-        module_name,
-        var_to_id_map,
-        missing_vars,
-        missing_ids,
-    })
+    Ok(())
 }
 
-fn write_to_file(parse_result: &ParseResult, mut output: BufWriter<File>) -> Result<(), io::Error> {
-    serde_yaml::to_writer(&mut output, parse_result)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    output.flush()
+/// Coverage across every parsed file, for `--stats-only`.
+fn print_stats(results: &[FileResult], per_module: bool) -> Result<(), io::Error> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let (mut modules, mut ids, mut matched, mut missing_vars, mut fuzzy) = (0, 0, 0, 0, 0);
+    for (path, parse_result) in results.iter().flatten() {
+        let module_matched = parse_result.var_to_id_map.len();
+        let module_ids = module_matched + parse_result.missing_ids.len();
+        if per_module {
+            writeln!(
+                stdout,
+                "{}: {} / {} IDs matched, {} missing variables",
+                path.display(),
+                module_matched,
+                module_ids,
+                parse_result.missing_vars.len()
+            )?;
+        }
+
+        modules += 1;
+        ids += module_ids;
+        matched += module_matched;
+        missing_vars += parse_result.missing_vars.len();
+        fuzzy += parse_result.fuzzy_matches.len();
+    }
+
+    if per_module && modules > 0 {
+        stdout.write_all(b"\n")?;
+    }
+    writeln!(stdout, "Modules: {modules}")?;
+    writeln!(stdout, "IDs: {ids}")?;
+    if ids > 0 {
+        let ratio = matched as f64 / ids as f64 * 100.0;
+        writeln!(stdout, "Matched: {matched} ({ratio:.1}%)")?;
+    } else {
+        writeln!(stdout, "Matched: {matched}")?;
+    }
+    writeln!(stdout, "Missing IDs: {}", ids - matched)?;
+    writeln!(stdout, "Missing variables: {missing_vars}")?;
+    writeln!(stdout, "Fuzzy matches: {fuzzy}")?;
+    writeln!(
+        stdout,
+        "Errors: {}",
+        results.iter().filter(|result| result.is_err()).count()
+    )?;
+
+    stdout.flush()
 }
 
-fn print_errors(
-    results: Vec<Result<(PathBuf, ParseResult), (PathBuf, Error)>>,
-) -> Result<(), std::io::Error> {
+fn print_errors(results: Vec<FileResult>) -> Result<(), std::io::Error> {
     let mut dirty = false;
 
     let stderr = std::io::stderr();
@@ -161,8 +161,9 @@ fn print_errors(
             Ok((path, parse_result)) => {
                 let ids_missing = !parse_result.missing_ids.is_empty();
                 let vars_missing = !parse_result.missing_vars.is_empty();
+                let fuzzy = !parse_result.fuzzy_matches.is_empty();
 
-                if ids_missing || vars_missing {
+                if ids_missing || vars_missing || fuzzy {
                     if dirty {
                         stderr.write_all(b"\n==========\n\n")?;
                     }
@@ -182,6 +183,13 @@ fn print_errors(
                         }
                     }
 
+                    if fuzzy {
+                        stderr.write_all(b"\nFuzzy matches:\n")?;
+                        for (variable, value, comment) in parse_result.fuzzy_matches.iter() {
+                            writeln!(stderr, "{variable} (\"{value}\" ~ \"{comment}\")")?;
+                        }
+                    }
+
                     dirty = true;
                 }
             }
@@ -205,32 +213,75 @@ fn print_errors(
 }
 
 fn main() -> Result<(), Error> {
-    let (input_dir, output_dir) = {
+    let (input_dir, output_dir, by_id, report, files) = {
         let mut args = env::args_os();
         let program_name = args.next().unwrap();
-
-        match (args.next(), args.next()) {
-            (Some(input_dir), Some(output_dir)) => (input_dir, PathBuf::from(output_dir)),
-            _ => {
-                eprintln!(
-                    "Usage: {} input_directory output_directory",
-                    program_name.to_string_lossy()
-                );
-                std::process::exit(1);
+        let print_usage_and_exit = || -> ! {
+            eprintln!(
+                "Usage: {0} [--files <PATTERN>] [--by-id <json|toml>] input_directory output_directory\n       {0} [--files <PATTERN>] --inspect input_directory\n       {0} [--files <PATTERN>] --stats-only [--per-module] input_directory",
+                program_name.to_string_lossy()
+            );
+            std::process::exit(1);
+        };
+
+        let mut report = None;
+        let mut per_module = false;
+        let mut by_id = None;
+        let mut files = None;
+        let mut positional = Vec::with_capacity(2);
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--inspect") => report = Some(Report::Inspect),
+                Some("--stats-only") => report = Some(Report::Stats { per_module: false }),
+                Some("--per-module") => per_module = true,
+                Some("--by-id") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("json") => by_id = Some(IdFormat::Json),
+                    Some("toml") => by_id = Some(IdFormat::Toml),
+                    _ => print_usage_and_exit(),
+                },
+                Some("--files") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(pattern) => files = Some(pattern.to_string()),
+                    None => print_usage_and_exit(),
+                },
+                _ => positional.push(arg),
             }
         }
+
+        let report = match report {
+            Some(Report::Stats { .. }) => Some(Report::Stats { per_module }),
+            _ if per_module => print_usage_and_exit(),
+            report => report,
+        };
+
+        // A bad pattern is reported before anything is read.
+        let files = glob_regex(files.as_deref().unwrap_or(DEFAULT_FILES))?;
+
+        let mut positional = positional.into_iter();
+        match (positional.next(), positional.next()) {
+            (Some(input_dir), Some(output_dir)) if report.is_none() => (
+                input_dir,
+                Some(PathBuf::from(output_dir)),
+                by_id,
+                report,
+                files,
+            ),
+            (Some(input_dir), None) if report.is_some() => (input_dir, None, by_id, report, files),
+            _ => print_usage_and_exit(),
+        }
     };
 
+    // Sorted, so that reports come out in the same order regardless of the filesystem.
     let file_paths = WalkDir::new(&input_dir)
+        .sort_by_file_name()
         .into_iter()
         .filter_map(|entry| match entry {
             Ok(entry) => {
-                let path = entry.into_path();
-                if path.ends_with("Trash.lua") {
-                    Some(Ok(path))
-                } else {
-                    None
-                }
+                let matches = entry.file_type().is_file()
+                    && entry
+                        .file_name()
+                        .to_str()
+                        .is_some_and(|name| files.is_match(name));
+                matches.then(|| Ok(entry.into_path()))
             }
             Err(err) => Some(Err(err)),
         })
@@ -239,30 +290,28 @@ fn main() -> Result<(), Error> {
     let results: Vec<_> = file_paths
         .into_par_iter()
         .map(|input_path| -> Result<_, (_, Error)> {
-            let output_path = {
-                input_path
-                    .strip_prefix(&input_dir)
-                    .map_err(|e| (input_path.clone(), From::from(e)))
-                    .and_then(|path| {
-                        path.parent().ok_or_else(|| {
-                            (
-                                input_path.clone(),
-                                "Failed to get a parent directory".into(),
-                            )
-                        })
-                    })
-                    .map(|path| output_dir.join(path).with_extension("yaml"))
-            }?;
-
-            if let Some(parent) = output_path.parent() {
-                fs::create_dir_all(parent).map_err(|e| (input_path.clone(), From::from(e)))?;
-            }
-
             let input = BufReader::new(
                 File::open(&input_path).map_err(|e| (input_path.clone(), From::from(e)))?,
             );
 
-            let parse_result = parse(input).map_err(|e| (input_path.clone(), From::from(e)))?;
+            let parse_result = parse(input).map_err(|e| (input_path.clone(), e))?;
+
+            // Nothing gets written with `--inspect` or `--stats-only`.
+            let Some(output_dir) = &output_dir else {
+                return Ok((input_path, parse_result));
+            };
+
+            let output_path = input_path
+                .strip_prefix(&input_dir)
+                .map_err(|e| (input_path.clone(), From::from(e)))
+                .map(|path| {
+                    let extension = by_id.map_or("yaml", IdFormat::extension);
+                    output_path(output_dir, path, extension)
+                })?;
+
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent).map_err(|e| (input_path.clone(), From::from(e)))?;
+            }
 
             if parse_result.var_to_id_map.is_empty() {
                 Ok((input_path, parse_result))
@@ -270,13 +319,28 @@ fn main() -> Result<(), Error> {
                 let output_file = BufWriter::new(
                     File::create(&output_path).map_err(|e| (input_path.clone(), From::from(e)))?,
                 );
-                write_to_file(&parse_result, output_file)
-                    .map_err(|e| (input_path.clone(), From::from(e)))
+                write_to_file(&parse_result, by_id, output_file)
+                    .map_err(|e| (input_path.clone(), e))
                     .map(|_| (input_path, parse_result))
             }
         })
         .collect();
 
+    match report {
+        Some(Report::Inspect) => {
+            let stdout = std::io::stdout();
+            let mut stdout = stdout.lock();
+            for (path, parse_result) in results.iter().flatten() {
+                let mut inspection = inspect(parse_result);
+                inspection["file"] = path.to_string_lossy().into();
+                writeln!(stdout, "{inspection}")?;
+            }
+            stdout.flush()?;
+        }
+        Some(Report::Stats { per_module }) => print_stats(&results, per_module)?,
+        None => {}
+    }
+
     match env::var_os("SHOW_MISSING_IDS_AND_VARS") {
         Some(ref value) if value == "1" => print_errors(results).map_err(From::from),
         _ => {
@@ -302,3 +366,50 @@ fn main() -> Result<(), Error> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        glob_regex(pattern).unwrap().is_match(name)
+    }
+
+    #[test]
+    fn glob_regex_matches_whole_file_names() {
+        assert!(matches("Trash.lua", "Trash.lua"));
+        assert!(!matches("Trash.lua", "MyTrash.lua"));
+        assert!(!matches("Trash.lua", "Trash.lua.bak"));
+        // The dot is literal.
+        assert!(!matches("Trash.lua", "Trash_lua"));
+        assert!(matches("*.lua", "Trash.lua"));
+        assert!(matches("*.lua", ".lua"));
+        assert!(matches("Trash?.lua", "Trash2.lua"));
+        assert!(!matches("Trash?.lua", "Trash.lua"));
+        assert!(matches("C++(1).lua", "C++(1).lua"));
+    }
+
+    #[test]
+    fn glob_regex_supports_classes() {
+        assert!(matches("[A-Z]*.lua", "Trash.lua"));
+        assert!(!matches("[A-Z]*.lua", "trash.lua"));
+        assert!(matches("[!A-Z]*.lua", "trash.lua"));
+        assert!(!matches("[!A-Z]*.lua", "Trash.lua"));
+        // A `]` right after the opening bracket (or the `!`) is a part of the class.
+        assert!(matches("[]a]*.lua", "]x.lua"));
+        assert!(matches("[!]]*.lua", "x.lua"));
+        assert!(!matches("[!]]*.lua", "]x.lua"));
+        // Regex syntax in a class is literal.
+        assert!(matches("[$^]*.lua", "^.lua"));
+        assert!(matches("[$^]*.lua", "$.lua"));
+        assert!(!matches("[$^]*.lua", "a.lua"));
+    }
+
+    #[test]
+    fn glob_regex_rejects_paths_and_unclosed_classes() {
+        assert!(glob_regex("Dungeons/*.lua").is_err());
+        assert!(glob_regex("Dungeons\\*.lua").is_err());
+        assert!(glob_regex("[A-Z*.lua").is_err());
+        assert!(glob_regex("[!").is_err());
+    }
+}
@@ -1,147 +1,21 @@
-use indexmap::IndexMap as Map;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use serde::Serialize;
+use module_parser::{inspect, parse, Error, IdFormat, ParseResult};
 use std::{
-    env, fmt,
+    env,
+    ffi::OsStr,
     fs::File,
-    io::{self, BufRead, BufReader, Write},
+    io::{self, BufReader, IsTerminal, Write},
 };
 
 #[cfg(all(target_env = "musl", target_pointer_width = "64"))]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
-
-#[derive(Serialize)]
-struct ParseResult {
-    module_name: Option<String>,
-    #[serde(rename = "npcs")]
-    var_to_id_map: Map<String, i64>,
-    #[serde(skip)]
-    missing_vars: Vec<(String, String)>,
-    #[serde(skip)]
-    missing_ids: Vec<(i64, String)>,
-}
-
-impl fmt::Debug for ParseResult {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("ParseResult")
-            .field("Variable-to-ID map", &self.var_to_id_map)
-            .field("Missing variables", &self.missing_vars)
-            .field("Missing IDs", &self.missing_ids)
-            .finish()
-    }
-}
-
-#[derive(Copy, Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
-enum ParseState {
-    ParsingIds,
-    ParsingVars,
-    Neither,
-}
-
-fn parse(mut file: BufReader<File>) -> Result<ParseResult, io::Error> {
-    static IDS_START: &str = "mod:RegisterEnableMob(";
-    static VARS_START: &str = "if L then";
-
-    static ID_REGEX: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r#"^\s*(\d+),?\s*--\s*(.+?)\n?$"#).unwrap());
-    static VAR_REGEX: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r#"^\s*L\.(\w+)\s*=\s*"(.+)""#).unwrap());
-    static MODULE_DECL_REGEX: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r#"^\s*local\s*mod(?:,\s*CL)?\s*=\s*BigWigs:NewBoss\("(.*?)""#).unwrap()
-    });
-
-    let mut ids_map = Map::with_capacity(16);
-    let mut vars_map = Map::with_capacity(16);
-    let mut module_name = None;
-
-    let mut state = ParseState::Neither;
-    let mut parsed_blocks = 0;
-
-    let mut line = String::new();
-    while file.read_line(&mut line)? > 0 {
-        match state {
-            ParseState::ParsingIds => match ID_REGEX.captures(&line) {
-                Some(caps) => {
-                    ids_map.insert(
-                        caps.get(2).unwrap().as_str().trim().to_string(),
-                        caps.get(1)
-                            .map(|cap| cap.as_str())
-                            .and_then(|cap| cap.parse::<i64>().ok())
-                            .unwrap(),
-                    );
-                }
-                None => {
-                    if line.find(')').is_some() {
-                        state = ParseState::Neither;
-                        if parsed_blocks == 1 {
-                            break;
-                        }
-                        parsed_blocks += 1;
-                    }
-                }
-            },
-            ParseState::ParsingVars => match VAR_REGEX.captures(&line) {
-                Some(caps) => {
-                    vars_map.insert(
-                        caps.get(2).unwrap().as_str().to_string(),
-                        caps.get(1).unwrap().as_str().to_string(),
-                    );
-                }
-                None => {
-                    if line.trim() == "end" {
-                        state = ParseState::Neither;
-                        if parsed_blocks == 1 {
-                            break;
-                        }
-                        parsed_blocks += 1;
-                    }
-                }
-            },
-            ParseState::Neither => {
-                if line.starts_with(IDS_START) {
-                    state = ParseState::ParsingIds;
-                } else if line.starts_with(VARS_START) {
-                    state = ParseState::ParsingVars;
-                } else if let Some(caps) = MODULE_DECL_REGEX.captures(&line) {
-                    module_name = caps.get(1).map(|v| v.as_str().into());
-                }
-            }
-        }
-        line.clear();
-    }
-
-    let mut var_to_id_map = Map::with_capacity(vars_map.len());
-    let mut missing_vars = Vec::with_capacity(4);
-
-    for (value, variable) in vars_map.into_iter() {
-        if let Some(id) = ids_map.remove(&value) {
-            var_to_id_map.insert(variable, id);
-        } else {
-            missing_vars.push((variable, value));
-        }
-    }
-
-    let missing_ids: Vec<_> = ids_map
-        .into_iter()
-        .map(|(comment, id)| (id, comment))
-        .collect();
-
-    Ok(ParseResult {
-        module_name,
-        var_to_id_map,
-        missing_vars,
-        missing_ids,
-    })
-}
-
-fn pretty_print(parse_result: ParseResult) -> Result<(), io::Error> {
+fn pretty_print(parse_result: ParseResult, by_id: Option<IdFormat>) -> Result<(), Error> {
     let mut stdout = io::stdout().lock();
-    serde_yaml::to_writer(&mut stdout, &parse_result)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    match by_id {
+        Some(format) => stdout.write_all(format.render(&parse_result)?.as_bytes())?,
+        None => serde_yaml::to_writer(&mut stdout, &parse_result)?,
+    }
     stdout.flush()?;
     drop(stdout);
 
@@ -161,23 +35,19 @@ fn pretty_print(parse_result: ParseResult) -> Result<(), io::Error> {
         }
     }
 
+    if !parse_result.fuzzy_matches.is_empty() {
+        stderr.write_all(b"\nFuzzy matches:\n")?;
+        for (variable, value, comment) in parse_result.fuzzy_matches.iter() {
+            writeln!(stderr, "{variable} (\"{value}\" ~ \"{comment}\")")?;
+        }
+    }
+
     Ok(())
 }
 
-fn main() -> Result<(), Error> {
-    let filename = {
-        let mut args = env::args_os();
-        let program_name = args.next().unwrap();
-
-        if let Some(filename) = args.next() {
-            filename
-        } else {
-            eprintln!("Usage: {} module.lua", program_name.to_string_lossy());
-            std::process::exit(1);
-        }
-    };
-
-    let file = File::open(&filename)?;
+/// Checks that `filename` is a file before parsing it, rather than failing on the first read.
+fn parse_file(filename: &OsStr) -> Result<ParseResult, Error> {
+    let file = File::open(filename)?;
     match file.metadata() {
         Ok(metadata) => {
             if !metadata.is_file() {
@@ -187,7 +57,53 @@ fn main() -> Result<(), Error> {
         Err(err) => return Err(err.into()),
     }
 
-    let result = parse(BufReader::new(file))?;
+    parse(BufReader::new(file))
+}
+
+fn main() -> Result<(), Error> {
+    let (filename, inspect_only, by_id) = {
+        let mut args = env::args_os();
+        let program_name = args.next().unwrap();
+        let print_usage_and_exit = || -> ! {
+            eprintln!(
+                "Usage: {} [--inspect] [--by-id <json|toml>] <module.lua|->",
+                program_name.to_string_lossy()
+            );
+            std::process::exit(1);
+        };
+
+        let mut inspect_only = false;
+        let mut by_id = None;
+        let mut filename = None;
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--inspect") => inspect_only = true,
+                Some("--by-id") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("json") => by_id = Some(IdFormat::Json),
+                    Some("toml") => by_id = Some(IdFormat::Toml),
+                    _ => print_usage_and_exit(),
+                },
+                _ => filename = Some(arg),
+            }
+        }
+
+        // `-` stands for stdin, which is also read when nothing else is given and it's piped.
+        match filename {
+            Some(filename) if filename == "-" => (None, inspect_only, by_id),
+            Some(filename) => (Some(filename), inspect_only, by_id),
+            None if !io::stdin().is_terminal() => (None, inspect_only, by_id),
+            None => print_usage_and_exit(),
+        }
+    };
+
+    let result = match filename {
+        Some(filename) => parse_file(&filename)?,
+        None => parse(io::stdin().lock())?,
+    };
+    if inspect_only {
+        println!("{}", serde_json::to_string_pretty(&inspect(&result))?);
+        return Ok(());
+    }
 
-    pretty_print(result).map_err(From::from)
+    pretty_print(result, by_id)
 }
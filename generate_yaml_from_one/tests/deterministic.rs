@@ -0,0 +1,234 @@
+use indexmap::IndexMap as Map;
+use localize_npc_names::{CsvDump, Localizer, Options, Schedule};
+use serde::Deserialize;
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+const MODULE: &str = r#"local mod, CL = BigWigs:NewBoss("Foo Trash", 1)
+mod:RegisterEnableMob(
+	1, -- Alpha
+	2, 3, -- Beta, Gamma
+	4, -- Delta
+	5, -- Epsilon
+	6 -- Zeta
+)
+local L = mod:GetLocale()
+if L then
+	L.zeta = "Zeta"
+	L.alpha = "Alpha"
+	L.delta = "Delta"
+	L.beta = "Beta"
+	L.gamma = "Gamma"
+end
+"#;
+
+const LOCALES: [&str; 3] = ["deDE", "frFR", "ruRU"];
+
+#[derive(Deserialize)]
+struct Input {
+    module_name: String,
+    npcs: Map<String, i64>,
+}
+
+/// A directory of its own under the system's temporary directory, empty.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "{}-{}-{name}",
+        env!("CARGO_PKG_NAME"),
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_csv_dump(dir: &Path) {
+    for locale in LOCALES {
+        let locale_dir = dir.join(locale);
+        fs::create_dir_all(&locale_dir).unwrap();
+        let rows: String = (1..=6)
+            .map(|id| format!("{id},Name {id} {locale}\n"))
+            .collect();
+        fs::write(
+            locale_dir.join("creature.csv"),
+            format!("ID,Name_lang\n{rows}"),
+        )
+        .unwrap();
+    }
+}
+
+/// Serves pages like Wowhead's NPC pages on a port of its own, `/de/npc=3` is named
+/// `Name 3 de`. Pages of lower IDs take longer, so that they're done out of order.
+fn serve_pages() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+
+                let path = request_line.split(' ').nth(1).unwrap_or_default();
+                let (subdomain, id) = path
+                    .trim_start_matches('/')
+                    .split_once("/npc=")
+                    .unwrap_or_default();
+                let id: u64 = id.parse().unwrap_or_default();
+                thread::sleep(Duration::from_millis(6_u64.saturating_sub(id) * 5));
+
+                let body = format!(
+                    "<html><body><h1 class=\"heading-size-1\">Name {id} {subdomain}</h1></body></html>"
+                );
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes());
+            });
+        }
+    });
+
+    port
+}
+
+/// Generates the YAML from `module_path` and puts a name in one of the locale files of
+/// `output_dir`, as if an earlier run had written it.
+fn generate(module_path: &Path, output_dir: &Path) -> (String, Input) {
+    let output = Command::new(env!("CARGO_BIN_EXE_generate_yaml_from_one"))
+        .arg(module_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let yaml = String::from_utf8(output.stdout).unwrap();
+    let input: Input = serde_yaml::from_str(&yaml).unwrap();
+
+    fs::write(
+        output_dir.join("deDE.lua"),
+        format!(
+            "local L = BigWigs:NewBossLocale(\"{}\", \"deDE\")\nif not L then return end\nif L then\n\tL.delta = \"Delta\"\nend\n",
+            input.module_name
+        ),
+    )
+    .unwrap();
+
+    (yaml, input)
+}
+
+/// Generates the YAML from `module_path` and localizes it into `output_dir`
+/// from the CSV dump in `csv_dir`.
+fn generate_and_localize(module_path: &Path, csv_dir: &Path, output_dir: &Path) -> String {
+    let (yaml, input) = generate(module_path, output_dir);
+
+    let source = CsvDump::open(csv_dir).unwrap();
+    let options = Options {
+        silent: true,
+        ..Default::default()
+    };
+    let report =
+        Localizer::run_with_source(&source, input.npcs, &input.module_name, output_dir, options)
+            .unwrap();
+    assert_eq!(report.failed, 0);
+
+    yaml
+}
+
+/// Every file in `dir` along with its contents, by name.
+fn read_files(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(&path).unwrap())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn runs_over_the_same_input_write_the_same_files() {
+    let fixtures = scratch_dir("fixtures");
+    let module_path = fixtures.join("Trash.lua");
+    fs::write(&module_path, MODULE).unwrap();
+    let csv_dir = fixtures.join("csv");
+    write_csv_dump(&csv_dir);
+
+    let first = scratch_dir("first");
+    let second = scratch_dir("second");
+    let first_yaml = generate_and_localize(&module_path, &csv_dir, &first);
+    let second_yaml = generate_and_localize(&module_path, &csv_dir, &second);
+
+    assert_eq!(first_yaml, second_yaml);
+    let files = read_files(&first);
+    assert_eq!(files.len(), LOCALES.len());
+    assert_eq!(files, read_files(&second));
+
+    // New names go after the existing one in the order of the module's variables.
+    let de = String::from_utf8(files[0].1.clone()).unwrap();
+    let order: Vec<_> = de
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("L.")?.split(' ').next())
+        .collect();
+    assert_eq!(order, ["delta", "zeta", "alpha", "beta", "gamma"]);
+
+    for dir in [fixtures, first, second] {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+#[test]
+fn runs_against_wowhead_write_the_same_files_with_either_schedule() {
+    let port = serve_pages();
+    let fixtures = scratch_dir("wowhead-fixtures");
+    let module_path = fixtures.join("Trash.lua");
+    fs::write(&module_path, MODULE).unwrap();
+
+    let mut runs = Vec::new();
+    for (i, schedule) in [Schedule::ByLanguage, Schedule::ByNpc]
+        .into_iter()
+        .cycle()
+        .take(4)
+        .enumerate()
+    {
+        let output_dir = scratch_dir(&format!("wowhead-{i}"));
+        let (_, input) = generate(&module_path, &output_dir);
+        // Dialing the fixture also keeps the cache out of it.
+        let options = Options {
+            silent: true,
+            base_url: Some(format!("http://127.0.0.1:{port}/{{subdomain}}")),
+            dial: Some(format!("tcp:127.0.0.1:{port}")),
+            locales: LOCALES.map(String::from).to_vec(),
+            requests_per_second: Some(1000.0),
+            schedule,
+            ..Default::default()
+        };
+        let report = Localizer::run(input.npcs, &input.module_name, &output_dir, options).unwrap();
+        assert_eq!(report.failed, 0);
+
+        runs.push(read_files(&output_dir));
+        let _ = fs::remove_dir_all(output_dir);
+    }
+
+    assert_eq!(runs[0].len(), LOCALES.len());
+    for files in &runs[1..] {
+        assert_eq!(files, &runs[0]);
+    }
+    let fr = String::from_utf8(runs[0][1].1.clone()).unwrap();
+    assert!(fr.contains("L.alpha = \"Name 1 fr\""));
+
+    let _ = fs::remove_dir_all(fixtures);
+}
@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use crate::{Localizer, Map, Options};
+
+/// A language a [`Localizer`] fetches and writes.
+#[derive(Debug, Clone)]
+pub struct Language {
+    /// A Wowhead subdomain (`de`), a full host (`wow.example.com`) or a base URL
+    /// (`https://example.com/tw`), empty if it has no source.
+    pub host: String,
+    /// Name of the locale file, e.g. `deDE`.
+    pub code: &'static str,
+    /// The line that opens the language's block in the locale file.
+    pub header: String,
+}
+
+impl Language {
+    /// A language with the usual `L = BigWigs:NewBossLocale("Module", "xxYY")` header.
+    pub fn new<S: Into<String>>(host: S, code: &'static str, module_name: &str) -> Self {
+        Self {
+            host: host.into(),
+            code,
+            header: format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"{code}\")"),
+        }
+    }
+}
+
+/// Sets up a [`Localizer`] with languages other than the default ones, e.g. private locales
+/// or a mirror of one. [`Localizer::run`] is a shortcut for a builder that's left as is.
+#[derive(Debug, Clone)]
+pub struct LocalizerBuilder {
+    ids_map: Map<String, i64>,
+    module_name: String,
+    output_dir: PathBuf,
+    languages: Option<Vec<Language>>,
+    extra_languages: Vec<Language>,
+    options: Options,
+}
+
+impl LocalizerBuilder {
+    pub(crate) fn new(ids_map: Map<String, i64>, module_name: &str) -> Self {
+        Self {
+            ids_map,
+            module_name: module_name.to_string(),
+            output_dir: PathBuf::new(),
+            languages: None,
+            extra_languages: Vec::new(),
+            options: Options::default(),
+        }
+    }
+
+    /// Where the locale files are, the current directory by default.
+    pub fn output_dir<P: Into<PathBuf>>(mut self, output_dir: P) -> Self {
+        self.output_dir = output_dir.into();
+        self
+    }
+
+    /// Replaces every option, so it should come before the setters of single ones.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// See [`Options::force_all`].
+    pub fn force_all(mut self, force_all: bool) -> Self {
+        self.options.force_all = force_all;
+        self
+    }
+
+    /// Replaces the default languages (the ones [`Options::locales`] selects).
+    pub fn languages(mut self, languages: Vec<Language>) -> Self {
+        self.languages = Some(languages);
+        self
+    }
+
+    /// Adds a language to the default ones (or to those given to
+    /// [`LocalizerBuilder::languages`]), replacing the one with the same code if there is one.
+    pub fn language(mut self, language: Language) -> Self {
+        self.extra_languages.push(language);
+        self
+    }
+
+    /// Works out what's left to fetch, [`Options::locale_aliases`] apply to every language.
+    /// Languages without a host are skipped.
+    pub fn build(self) -> Localizer {
+        let options = self.options;
+        let languages = self.languages.unwrap_or_else(|| {
+            Localizer::languages(&self.module_name, &options.locales)
+                .into_iter()
+                .map(|language| Language {
+                    host: language.0.to_string(),
+                    code: language.1,
+                    header: language.2,
+                })
+                .collect()
+        });
+
+        let mut by_code = Map::new();
+        for language in languages.into_iter().chain(self.extra_languages) {
+            by_code.insert(language.code, language);
+        }
+
+        let mut hosts = Map::new();
+        let mut headers = Vec::new();
+        for (code, language) in by_code {
+            let host = options
+                .locale_aliases
+                .get(code)
+                .cloned()
+                .unwrap_or(language.host);
+            if !host.is_empty() {
+                hosts.insert(code, host);
+                headers.push((code, language.header));
+            }
+        }
+
+        Localizer::new(
+            headers,
+            &self.ids_map,
+            &self.module_name,
+            self.output_dir,
+            hosts,
+            options,
+        )
+    }
+}
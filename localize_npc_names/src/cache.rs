@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{utils, Map};
+
+/// Names are fetched again once they're older than this.
+pub(crate) const DEFAULT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const FILE_NAME: &str = "wowhead.json";
+
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    name: String,
+    /// The ID the page ended up at, if it's known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    /// Seconds since the Unix epoch.
+    fetched: u64,
+    /// Size of the page(s) the name was taken from, unknown for names cached by older versions.
+    #[serde(default)]
+    bytes: u64,
+}
+
+/// How much a cache was used, see [`crate::NameSource::cache_stats`].
+///
+/// Only names are cached, not pages, so an expired name is simply fetched again: no request
+/// is ever conditional and there are no revalidations (304 responses) to count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Names that were taken from the cache.
+    pub hits: usize,
+    /// Names that weren't cached (or had expired) and had to be fetched.
+    pub misses: usize,
+    /// Size of the pages that didn't have to be downloaded thanks to the hits.
+    pub bytes_saved: u64,
+}
+
+impl CacheStats {
+    /// What was counted since `earlier` was taken.
+    pub fn since(self, earlier: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits - earlier.hits,
+            misses: self.misses - earlier.misses,
+            bytes_saved: self.bytes_saved - earlier.bytes_saved,
+        }
+    }
+
+    /// Adds up the stats of several runs.
+    pub fn merge(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.bytes_saved += other.bytes_saved;
+    }
+}
+
+/// Names fetched by earlier runs, keyed by URL (which has the subdomain, the kind and the ID)
+/// and the options that change what's taken from the page.
+/// Loaded when it's opened and written back on [`Cache::write`].
+pub(crate) struct Cache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: Mutex<Map<String, Cached>>,
+    changed: AtomicBool,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    bytes_saved: AtomicU64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// `CACHE_DIR` if it's set, otherwise the platform's cache directory.
+fn default_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(base.join(env!("CARGO_PKG_NAME")))
+}
+
+impl Cache {
+    /// Returns `None` if `NO_CACHE=1` is set or there's nowhere to put the cache.
+    /// A cache that can't be read is started over.
+    pub(crate) fn open(ttl: Duration) -> Option<Self> {
+        if env::var_os("NO_CACHE").is_some_and(|value| value == "1") {
+            return None;
+        }
+
+        let path = default_dir()?.join(FILE_NAME);
+        let entries = File::open(&path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default();
+
+        Some(Self {
+            path,
+            ttl,
+            entries: Mutex::new(entries),
+            changed: AtomicBool::new(false),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            bytes_saved: AtomicU64::new(0),
+        })
+    }
+
+    /// The name and the ID the page ended up at, unless there's none or it's expired.
+    /// Counts as a hit or a miss.
+    pub(crate) fn get(&self, url: &str) -> Option<(String, Option<i64>)> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries
+            .get(url)
+            .filter(|cached| Duration::from_secs(now().saturating_sub(cached.fetched)) < self.ttl);
+
+        match cached {
+            Some(cached) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                self.bytes_saved.fetch_add(cached.bytes, Ordering::Relaxed);
+                Some((cached.name.clone(), cached.id))
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// `bytes` is the size of the page(s) the name was taken from.
+    pub(crate) fn insert(&self, url: &str, name: &str, id: Option<i64>, bytes: u64) {
+        let cached = Cached {
+            name: name.to_string(),
+            id,
+            fetched: now(),
+            bytes,
+        };
+        self.entries.lock().unwrap().insert(url.to_string(), cached);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            bytes_saved: self.bytes_saved.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Writes the cache back if anything was added, expired names are dropped.
+    pub(crate) fn write(self) -> io::Result<()> {
+        if !self.changed.into_inner() {
+            return Ok(());
+        }
+
+        let mut entries = self.entries.into_inner().unwrap();
+        let now = now();
+        entries
+            .retain(|_, cached| Duration::from_secs(now.saturating_sub(cached.fetched)) < self.ttl);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Another run may be reading (or writing) it, so it's replaced in one go.
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let tmp_path = utils::tmp_path(dir, &self.path, "wowhead");
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+            .and_then(|file| {
+                let mut file = BufWriter::new(file);
+                serde_json::to_writer(&mut file, &entries)?;
+                file.flush()
+            })
+            .and_then(|()| fs::rename(&tmp_path, &self.path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+
+        written
+    }
+}
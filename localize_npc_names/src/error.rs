@@ -1,8 +1,58 @@
-use std::{io, path::PathBuf};
+use std::{fmt, io, path::PathBuf};
 
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 pub(crate) enum ProcessingError {
     IoError((PathBuf, io::Error)),
     DataError((&'static str, String, Error)),
+    /// Something that didn't stop a name from being written, but should be looked at.
+    Warning(String),
 }
+
+/// Wowhead responded with a 503 or a maintenance page.
+#[derive(Debug)]
+pub(crate) struct SiteUnavailable;
+
+impl fmt::Display for SiteUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Wowhead is unavailable")
+    }
+}
+
+impl std::error::Error for SiteUnavailable {}
+
+/// The page had a name heading, but it was empty.
+#[derive(Debug)]
+pub(crate) struct EmptyName;
+
+impl fmt::Display for EmptyName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The name is empty")
+    }
+}
+
+impl std::error::Error for EmptyName {}
+
+/// The page lists several entities sharing a name instead of describing one.
+#[derive(Debug)]
+pub(crate) struct Ambiguous;
+
+impl fmt::Display for Ambiguous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The ID leads to a disambiguation page listing several entities")
+    }
+}
+
+impl std::error::Error for Ambiguous {}
+
+/// Cloudflare served a challenge page ("Just a moment...") instead of the one requested.
+#[derive(Debug)]
+pub(crate) struct Blocked;
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Blocked by a Cloudflare challenge page, the ID itself may well be valid")
+    }
+}
+
+impl std::error::Error for Blocked {}
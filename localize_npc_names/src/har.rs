@@ -0,0 +1,229 @@
+use isahc::http::{HeaderMap, StatusCode, Version};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Bodies are cut down to this many bytes, the tool only cares about the heading anyway.
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    method: &'static str,
+    url: String,
+    http_version: String,
+    headers: Vec<Header>,
+    query_string: Vec<Header>,
+    cookies: Vec<Header>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: usize,
+    mime_type: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    headers: Vec<Header>,
+    cookies: Vec<Header>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    started_date_time: String,
+    time: f64,
+    request: Request,
+    response: Response,
+    cache: serde_json::Map<String, serde_json::Value>,
+    timings: Timings,
+}
+
+/// Collects every request made during a run and writes them as an HTTP Archive.
+pub(crate) struct Recorder {
+    request_headers: Vec<(&'static str, String)>,
+    entries: Mutex<Vec<Entry>>,
+}
+
+/// Everything about an exchange that isn't known until the body has been read.
+pub(crate) struct Exchange<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) started: SystemTime,
+    pub(crate) wait: Duration,
+    pub(crate) receive: Duration,
+    pub(crate) status: StatusCode,
+    pub(crate) version: Version,
+    pub(crate) headers: &'a HeaderMap,
+    pub(crate) body: &'a str,
+}
+
+fn to_headers(headers: &HeaderMap) -> Vec<Header> {
+    headers
+        .iter()
+        .map(|(name, value)| Header {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Formats a timestamp as ISO 8601 in UTC, e.g. `2024-03-01T12:34:56.789Z`.
+pub(crate) fn iso_8601(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Howard Hinnant's `civil_from_days`.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+impl Recorder {
+    /// `request_headers` are recorded for every request, since the client doesn't expose them.
+    pub(crate) fn new(request_headers: Vec<(&'static str, String)>) -> Self {
+        Self {
+            request_headers,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, exchange: Exchange) {
+        let http_version = format!("{:?}", exchange.version);
+        let mime_type = exchange
+            .headers
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let mut text = exchange.body.to_string();
+        if text.len() > MAX_BODY_LEN {
+            let boundary = (0..=MAX_BODY_LEN)
+                .rev()
+                .find(|&i| text.is_char_boundary(i))
+                .unwrap_or(0);
+            text.truncate(boundary);
+        }
+
+        let entry = Entry {
+            started_date_time: iso_8601(exchange.started),
+            time: millis(exchange.wait + exchange.receive),
+            request: Request {
+                method: "GET",
+                url: exchange.url.to_string(),
+                http_version: http_version.clone(),
+                headers: self
+                    .request_headers
+                    .iter()
+                    .map(|(name, value)| Header {
+                        name: name.to_string(),
+                        value: value.clone(),
+                    })
+                    .collect(),
+                query_string: Vec::new(),
+                cookies: Vec::new(),
+                headers_size: -1,
+                body_size: 0,
+            },
+            response: Response {
+                status: exchange.status.as_u16(),
+                status_text: exchange
+                    .status
+                    .canonical_reason()
+                    .unwrap_or_default()
+                    .to_string(),
+                http_version,
+                headers: to_headers(exchange.headers),
+                cookies: Vec::new(),
+                content: Content {
+                    size: exchange.body.len(),
+                    mime_type,
+                    text,
+                },
+                redirect_url: String::new(),
+                headers_size: -1,
+                body_size: exchange.body.len() as i64,
+            },
+            cache: serde_json::Map::new(),
+            timings: Timings {
+                send: 0.0,
+                wait: millis(exchange.wait),
+                receive: millis(exchange.receive),
+            },
+        };
+
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    pub(crate) fn write(self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries.into_inner().unwrap();
+        entries.sort_by(|a, b| a.started_date_time.cmp(&b.started_date_time));
+
+        let har = serde_json::json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "entries": entries,
+            }
+        });
+
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, &har)?;
+        file.flush()
+    }
+}
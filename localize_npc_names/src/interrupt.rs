@@ -0,0 +1,62 @@
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes runs stop fetching: names that were fetched by then are still written, the rest
+/// aren't fetched at all. It can't be undone.
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+pub(crate) fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Makes Ctrl-C [`cancel`] runs instead of killing the process, so that partially fetched
+/// languages still get written. A second Ctrl-C exits right away.
+#[cfg(unix)]
+pub fn install_interrupt_handler() -> io::Result<()> {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            // Only async-signal-safe functions may be called here.
+            unsafe { libc::_exit(130) };
+        }
+    }
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Makes Ctrl-C [`cancel`] runs instead of killing the process, so that partially fetched
+/// languages still get written. A second Ctrl-C exits right away.
+#[cfg(windows)]
+pub fn install_interrupt_handler() -> io::Result<()> {
+    use winapi::{
+        shared::minwindef::{BOOL, DWORD, FALSE, TRUE},
+        um::{
+            consoleapi::SetConsoleCtrlHandler,
+            wincon::{CTRL_BREAK_EVENT, CTRL_C_EVENT},
+        },
+    };
+
+    unsafe extern "system" fn on_interrupt(ctrl_type: DWORD) -> BOOL {
+        match ctrl_type {
+            // Returning FALSE hands the second one over to the default handler, which exits.
+            CTRL_C_EVENT | CTRL_BREAK_EVENT if !CANCELLED.swap(true, Ordering::SeqCst) => TRUE,
+            _ => FALSE,
+        }
+    }
+
+    if unsafe { SetConsoleCtrlHandler(Some(on_interrupt), TRUE) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -1,93 +1,1252 @@
 use crossbeam_channel as channel;
 use indexmap::IndexMap as Map;
 use isahc::{
-    config::{Configurable, RedirectPolicy},
+    config::{CaCertificate, Configurable, Dialer, RedirectPolicy},
     HttpClient,
 };
 use once_cell::sync::Lazy;
 use onig::Regex;
 use rayon::prelude::*;
-use select::{
-    document::Document,
-    predicate::{Class, Name},
-};
+use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
-    env,
-    fs::File,
-    io::{BufReader, Write},
+    env, fmt,
+    fs::{self, File},
+    io::{self, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+mod builder;
+pub use builder::{Language, LocalizerBuilder};
+mod cache;
+pub use cache::CacheStats;
 mod error;
 pub use error::Error;
-use error::ProcessingError;
+mod har;
+mod interrupt;
+pub use interrupt::{cancel, install_interrupt_handler};
+#[doc(hidden)]
+#[macro_use]
+pub mod logging;
+use error::{EmptyName, ProcessingError, SiteUnavailable};
+pub use logging::log_to_file;
+mod output;
+pub use output::OutputKind;
+mod resume;
+use resume::Checkpoint;
+mod source;
+pub use source::{CsvDump, NameSource, Wowhead};
 mod utils;
+use utils::Entry;
 
 const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/122.0.0.0 Safari/537.3";
-static USER_AGENT: Lazy<Cow<'static, str>> = Lazy::new(|| {
+
+fn default_user_agent() -> Cow<'static, str> {
     env::var("USER_AGENT")
         .map(Cow::from)
         .unwrap_or_else(|_| Cow::from(DEFAULT_USER_AGENT))
-});
+}
+
+/// Number of consecutive 503s or maintenance pages after which we stop making requests.
+const OUTAGE_THRESHOLD: usize = 5;
+
+/// Locales only processed when they're asked for, see [`Options::locales`].
+const OPTIONAL_LOCALES: &[&str] = &["esMX"];
+
+const DEFAULT_MAX_RETRIES: usize = 3;
+
+fn default_max_retries() -> usize {
+    env::var("MAX_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RETRIES)
+}
+
+const DEFAULT_REQUESTS_PER_SECOND: f64 = 5.0;
+
+fn default_requests_per_second() -> f64 {
+    env::var("REQUESTS_PER_SECOND")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&rate: &f64| rate > 0.0)
+        .unwrap_or(DEFAULT_REQUESTS_PER_SECOND)
+}
+
+/// Token buckets keyed by host, shared by every worker so that no subdomain gets more than
+/// its share of requests however many languages are fetched at once.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    buckets: Mutex<Map<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(requests_per_second: f64) -> Self {
+        Self {
+            rate: requests_per_second,
+            buckets: Mutex::new(Map::new()),
+        }
+    }
+
+    /// Blocks until a request to `host` may be made.
+    pub(crate) fn acquire(&self, host: &str) {
+        // Up to a second worth of requests may go out at once.
+        let capacity = self.rate.max(1.0);
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let now = Instant::now();
+                let (tokens, updated) = buckets.entry(host.to_string()).or_insert((capacity, now));
+                *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * self.rate)
+                    .min(capacity);
+                *updated = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Default of [`Options::max_inflight`] for every language that's fetched.
+const INFLIGHT_PER_LANGUAGE: usize = 4;
+
+fn default_max_inflight(languages: usize) -> usize {
+    env::var("MAX_INFLIGHT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&limit: &usize| limit > 0)
+        .unwrap_or(languages.max(1) * INFLIGHT_PER_LANGUAGE)
+}
+
+/// Bounds the number of requests in flight across every worker, so that a run with many
+/// languages doesn't run out of sockets. Rayon's threads only ever wait on it while holding
+/// no permit, so it can't deadlock.
+pub(crate) struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Gives its permit back on drop.
+pub(crate) struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    pub(crate) fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a permit is available.
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let mut available = self
+            .released
+            .wait_while(self.available.lock().unwrap(), |available| *available == 0)
+            .unwrap();
+        *available -= 1;
+
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// How many more times a page with an empty name is requested when `retry_on_empty` is set.
+const EMPTY_NAME_RETRIES: usize = 3;
+
+/// What an ID refers to, each kind has its own pages on Wowhead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityKind {
+    #[default]
+    Npc,
+    Achievement,
+}
+
+impl EntityKind {
+    fn path(self) -> &'static str {
+        match self {
+            EntityKind::Npc => "npc",
+            EntityKind::Achievement => "achievement",
+        }
+    }
+}
+
+impl fmt::Display for EntityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityKind::Npc => f.write_str("NPC"),
+            EntityKind::Achievement => f.write_str("achievement"),
+        }
+    }
+}
+
+/// Writes a record as a single line, so that concurrent records don't get interleaved.
+fn emit_jsonl(record: serde_json::Value) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{record}");
+    let _ = stdout.flush();
+}
 
 #[derive(Debug, Clone)]
 pub struct LanguageData {
-    subdomain: &'static str,
     code: &'static str,
     header: String,
     ids_map: Map<String, i64>,
 }
 
+fn serialize_secs<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer.serialize_u64(duration.as_secs()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_secs_seq<S: serde::Serializer>(
+    durations: &[Duration],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(durations.iter().map(Duration::as_secs))
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Options {
+    /// Fetch every string, even those already present in the locale files.
+    pub force_all: bool,
+    /// Sent as the `From` header so site operators can reach whoever runs the tool.
+    pub identify: Option<String>,
+    /// Names longer than this many bytes get cut on a char boundary and end with an ellipsis.
+    pub truncate_names: Option<usize>,
+    /// Overrides Wowhead subdomains, keyed by locale code (e.g. `deDE` -> `de`).
+    pub locale_aliases: Map<String, String>,
+    /// Append the NPC ID as a comment to every written line.
+    pub annotate_ids: bool,
+    /// Order assignments the same way NPCs are ordered in the input.
+    pub input_order: bool,
+    /// Order assignments by variable name, existing ones included. Ignored with
+    /// [`Options::input_order`].
+    pub sort_names: bool,
+    /// Falls back to the `USER_AGENT` env variable and then to a recent Chrome's UA.
+    pub user_agent: Option<String>,
+    /// Languages where a smaller share of names was fetched aren't written at all.
+    pub min_translated_ratio: Option<f64>,
+    /// Request a page again if its name heading came back empty.
+    pub retry_on_empty: bool,
+    /// Skip `fsync()` calls, trading crash safety for speed.
+    pub no_fsync: bool,
+    /// Copy locale files to `deDE.lua.bak` and so on before changing them.
+    pub backup: bool,
+    /// Print a unified diff of every locale file to stdout instead of writing it.
+    pub dry_run: bool,
+    /// Write locale files here instead of overwriting the ones in the output directory.
+    /// Files that are already there are merged into, so that several modules add up.
+    pub staging_dir: Option<PathBuf>,
+    /// Written before invalid entries instead of `-- `, has to start with `--`.
+    pub invalid_prefix: Option<String>,
+    /// Indentation of written lines instead of a tab, e.g. four spaces. Files that already have
+    /// indented names keep the indentation they use.
+    pub indent: Option<String>,
+    /// Record every request and response into an HTTP Archive at this path.
+    pub har: Option<PathBuf>,
+    /// Print a JSON object to stdout for every NPC as soon as it's done.
+    pub jsonl: bool,
+    /// Kinds of entities keyed by variable name, anything that isn't listed is an NPC.
+    pub kinds: Map<String, EntityKind>,
+    /// Stop making requests after this long, whatever was fetched by then still gets written.
+    #[serde(
+        serialize_with = "serialize_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_runtime: Option<Duration>,
+    /// Only fetch names that are commented out as invalid in the existing locale files.
+    pub only_invalid: bool,
+    /// Don't print the progress line.
+    pub quiet: bool,
+    /// Don't print the progress line, names that couldn't be fetched or warnings while running,
+    /// for callers that report from the returned [`RunReport`] themselves.
+    pub silent: bool,
+    /// Warn about IDs that resolve to different pages depending on the language.
+    pub dedupe_across_languages: bool,
+    /// Keep names already in the locale files when the fetched ones differ, see [`KeptName`].
+    pub prefer_existing: bool,
+    /// Only add names that aren't in the locale files yet, existing lines are never touched.
+    pub append_only: bool,
+    /// Put at the start of every Lua locale file (e.g. a license), unless it's already there.
+    pub preamble: Option<String>,
+    /// Put at the end of every Lua locale file, after every locale block.
+    pub epilogue: Option<String>,
+    /// Start every Lua locale file with a comment that says how many names are localized,
+    /// replaced whenever the file changes.
+    pub coverage_comment: bool,
+    /// Locale codes to process, empty means every locale but the optional ones (esMX).
+    /// [`Localizer::run`] and [`Localizer::fetch`] fail if there's an unknown one.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locales: Vec<String>,
+    /// Locale codes to process first, in this order, the rest follow in the default order.
+    pub language_order: Vec<String>,
+    /// Prefixes (e.g. ranks or articles) to remove from fetched names, keyed by locale code.
+    /// Only the first one that matches is removed.
+    pub strip_prefixes: Map<String, Vec<String>>,
+    /// Where pages are requested from, `{subdomain}` gets replaced with the locale's subdomain.
+    /// Defaults to the database of [`Options::game_version`], e.g. `https://{subdomain}.wowhead.com`.
+    pub base_url: Option<String>,
+    /// Connect here instead of the host in the URL, e.g. `unix:/run/mirror.sock` or
+    /// `tcp:127.0.0.1:8080`. The `Host` header still comes from the URL.
+    pub dial: Option<String>,
+    /// PEM file with the certificates to trust instead of the system's, e.g. the CA of
+    /// a TLS-intercepting proxy. Only applies to the client built by [`Localizer::run`].
+    pub ca_cert: Option<PathBuf>,
+    /// Names (a regex, e.g. `^Guard$`) too generic to be used; when a page's name matches,
+    /// the page's canonical link is followed and the name is taken from there instead.
+    pub follow_canonical: Option<String>,
+    /// Check that ruRU, koKR and zhCN names contain characters of their script.
+    pub script_check: Option<ScriptCheck>,
+    /// Append the value of invalid names as it was fetched (e.g. `[Name]`) as a comment.
+    pub keep_raw_invalid: bool,
+    /// How invalid names are written into the locale files, commented out by default.
+    pub on_invalid: OnInvalid,
+    /// What locale files look like.
+    pub output_format: OutputKind,
+    /// Whether names are fetched a language or an NPC at a time.
+    pub schedule: Schedule,
+    /// Which game's database names come from. Falls back to the `GAME_VERSION` env variable
+    /// and then to retail. Ignored when [`Options::base_url`] is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_version: Option<GameVersion>,
+    /// Give up on a language after this many names couldn't be fetched, the others go on.
+    pub max_failures_per_language: Option<usize>,
+    /// Also look for names in the data embedded in Wowhead pages (JSON-LD or `g_pageInfo`).
+    pub embedded_data: Option<EmbeddedData>,
+    /// Classes of the elements a name is looked for in, tried in order until one has some text.
+    /// The first one is the page heading, empty means `["heading-size-1"]`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub name_selectors: Vec<String>,
+    /// What to do with names that contain color codes (`|cffffffff...|r`).
+    pub color_codes: ColorCodes,
+    /// Timeout of every attempt at a request, a request that times out is made again with the
+    /// next one until they run out. Empty means a single attempt with the client's timeout.
+    #[serde(
+        serialize_with = "serialize_secs_seq",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub timeouts: Vec<Duration>,
+    /// How many more times a request that failed on the network or with a 5xx is made, with
+    /// a growing delay. Falls back to the `MAX_RETRIES` env variable and then to 3.
+    pub max_retries: Option<usize>,
+    /// Requests per second to each Wowhead subdomain. Falls back to the `REQUESTS_PER_SECOND`
+    /// env variable and then to 5.
+    pub requests_per_second: Option<f64>,
+    /// Requests that may be in flight at once, to every subdomain together. Falls back to the
+    /// `MAX_INFLIGHT` env variable and then to 4 per language.
+    pub max_inflight: Option<usize>,
+    /// Names cached by earlier runs are used until they're this old, 7 days by default.
+    /// The cache is skipped with `NO_CACHE=1` and when [`Options::dial`] is set.
+    #[serde(
+        serialize_with = "serialize_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cache_ttl: Option<Duration>,
+}
+
+/// The order names are fetched in, the written files are the same either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Schedule {
+    /// Every language is processed on its own and written as soon as it's done.
+    #[default]
+    ByLanguage,
+    /// Every locale of an NPC is fetched at the same time, files are written at the end.
+    ByNpc,
+}
+
+/// A version of the game with a database of its own on Wowhead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GameVersion {
+    #[default]
+    Retail,
+    Classic,
+    Cata,
+    /// Season of Discovery, which shares its database with Classic.
+    Sod,
+}
+
+impl GameVersion {
+    /// Parses the names used on the command line and in `GAME_VERSION`, e.g. `cata`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "retail" => Some(Self::Retail),
+            "classic" => Some(Self::Classic),
+            "cata" => Some(Self::Cata),
+            "sod" => Some(Self::Sod),
+            _ => None,
+        }
+    }
+
+    /// Where pages are requested from, `{subdomain}` gets replaced with the locale's subdomain.
+    /// Classic databases moved from subdomains to paths, where the language is a path segment
+    /// named the same way.
+    pub(crate) fn base_url(self) -> &'static str {
+        match self {
+            Self::Retail => "https://{subdomain}.wowhead.com",
+            Self::Classic | Self::Sod => "https://www.wowhead.com/classic/{subdomain}",
+            Self::Cata => "https://www.wowhead.com/cata/{subdomain}",
+        }
+    }
+}
+
+fn default_game_version() -> GameVersion {
+    env::var("GAME_VERSION")
+        .ok()
+        .and_then(|value| GameVersion::from_name(&value))
+        .unwrap_or_default()
+}
+
+/// When the name embedded in a page's data is used instead of its heading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddedData {
+    /// Whenever there is one, the heading is only used if there isn't.
+    First,
+    /// Only if the heading is missing or empty.
+    Fallback,
+}
+
+/// What to do with a name that contains color codes, which are only meant for the game's UI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorCodes {
+    /// Write the name as it is.
+    #[default]
+    Keep,
+    /// Remove the color codes, keeping the text they color.
+    Strip,
+    /// Remove the color codes and comment the name out.
+    Invalid,
+}
+
+/// How to write a name that Wowhead considers invalid, e.g. `[Name]`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnInvalid {
+    /// Write it commented out, so that it's fetched again next time.
+    #[default]
+    Comment,
+    /// Leave it out of the file.
+    Skip,
+    /// Write it the same way as a valid one.
+    Keep,
+}
+
+/// What to do with a name that isn't written in the script its locale uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptCheck {
+    /// Print a warning, the name is still written.
+    Warn,
+    /// Print a warning and comment the name out.
+    Invalid,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunReport {
+    /// Languages that had work to do and were handed over to be written.
+    pub processed: Vec<&'static str>,
+    /// Languages that stopped being fetched because of [`Options::max_failures_per_language`].
+    pub aborted: Vec<&'static str>,
+    /// Languages that weren't written because of [`Options::min_translated_ratio`],
+    /// along with the ratio they reached.
+    pub below_min_ratio: Vec<(&'static str, f64)>,
+    /// What happened to the locale file of every processed language.
+    pub files: Vec<(&'static str, FileStatus)>,
+    /// [`Options::max_runtime`] ran out before every name was fetched.
+    pub timed_out: bool,
+    /// The run was cancelled (e.g. with Ctrl-C) before every name was fetched, see [`cancel`].
+    pub cancelled: bool,
+    /// Number of names there were to fetch.
+    pub total: usize,
+    /// Number of names that were fetched.
+    pub succeeded: usize,
+    /// Number of names that couldn't be fetched plus locale files that couldn't be written.
+    pub failed: usize,
+    /// Names that couldn't be fetched: language, variable name and the error.
+    pub failures: Vec<(&'static str, String, String)>,
+    /// Everything that was printed as a warning while running.
+    pub warnings: Vec<String>,
+    /// Names that were left alone because of [`Options::prefer_existing`], by language.
+    pub kept_existing: Vec<(&'static str, KeptName)>,
+    /// How much the cache was used, for sources that have one (see [`NameSource::cache_stats`]).
+    pub cache: Option<CacheStats>,
+}
+
+/// An existing name that differs from the fetched one.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeptName {
+    pub name: String,
+    pub existing: String,
+    pub fetched: String,
+}
+
+impl RunReport {
+    /// Adds up the reports of several runs, e.g. one per module.
+    pub fn merge(&mut self, other: RunReport) {
+        for code in other.processed {
+            if !self.processed.contains(&code) {
+                self.processed.push(code);
+            }
+        }
+        for code in other.aborted {
+            if !self.aborted.contains(&code) {
+                self.aborted.push(code);
+            }
+        }
+        self.below_min_ratio.extend(other.below_min_ratio);
+        self.files.extend(other.files);
+        self.timed_out |= other.timed_out;
+        self.cancelled |= other.cancelled;
+        self.total += other.total;
+        self.succeeded += other.succeeded;
+        self.failed += other.failed;
+        self.failures.extend(other.failures);
+        self.warnings.extend(other.warnings);
+        self.kept_existing.extend(other.kept_existing);
+        self.cache = match (self.cache, other.cache) {
+            (Some(mut cache), Some(other)) => {
+                cache.merge(other);
+                Some(cache)
+            }
+            (cache, other) => cache.or(other),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Unchanged,
+    Updated,
+    Created,
+}
+
+/// Fetched names keyed by locale code and then by variable name (or by ID, see
+/// [`Translations::key_by_id`]). Names Wowhead marks as invalid keep their surrounding brackets.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Translations {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module_name: Option<String>,
+    /// Names are keyed by ID, such dumps can't be applied.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyed_by_id: bool,
+    #[serde(flatten)]
+    pub locales: Map<String, Map<String, String>>,
+}
+
+impl Translations {
+    /// Replaces variable names with the IDs they map to in `ids_map`, for tools that look names
+    /// up by ID. Variables that aren't in `ids_map` are dropped.
+    pub fn key_by_id(self, ids_map: &Map<String, i64>) -> Self {
+        let locales = self
+            .locales
+            .into_iter()
+            .map(|(locale, names)| {
+                let names = names
+                    .into_iter()
+                    .filter_map(|(name, translation)| {
+                        Some((ids_map.get(&name)?.to_string(), translation))
+                    })
+                    .collect();
+                (locale, names)
+            })
+            .collect();
+
+        Self {
+            keyed_by_id: true,
+            locales,
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Localizer {
     data: Vec<LanguageData>,
+    options: Options,
+    module_name: String,
     output_dir: PathBuf,
+    /// Positions of the names in the input, see [`Options::input_order`].
+    order: Option<Map<String, usize>>,
+    /// Where the languages that don't come from Wowhead's defaults are fetched from.
+    hosts: Map<&'static str, String>,
 }
 
 impl Localizer {
+    /// Fetches names from Wowhead and writes them to the locale files in `output_dir`.
+    /// Progress and names that couldn't be fetched are printed to stderr as they come in,
+    /// unless [`Options::silent`] is set, and are all in the returned [`RunReport`] either way.
     pub fn run<P: Into<PathBuf>>(
         ids_map: Map<String, i64>,
         module_name: &str,
         output_dir: P,
-        force_all: bool,
-    ) {
-        let output_dir = output_dir.into();
-        let localizer = Self {
-            data: Self::construct_language_data(vec![
-                // ("www", "enUS", String::from("L = mod:GetLocale()")),
-                ("de", "deDE", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"deDE\")")),
-                ("es", "esES", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"esES\") or BigWigs:NewBossLocale(\"{module_name}\", \"esMX\")")),
-                ("fr", "frFR", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"frFR\")")),
-                ("it", "itIT", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"itIT\")")),
-                ("pt", "ptBR", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"ptBR\")")),
-                ("ru", "ruRU", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"ruRU\")")),
-                ("ko", "koKR", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"koKR\")")),
-                ("cn", "zhCN", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"zhCN\")")),
-            ], &ids_map, if force_all { None } else { Some(&output_dir) }),
+        options: Options,
+    ) -> Result<RunReport, Error> {
+        Self::check_locales(&options.locales)?;
+        Self::builder(ids_map, module_name)
+            .output_dir(output_dir)
+            .options(options)
+            .build()
+            .execute()
+    }
+
+    /// For languages other than the default ones, see [`LocalizerBuilder`].
+    pub fn builder(ids_map: Map<String, i64>, module_name: &str) -> LocalizerBuilder {
+        LocalizerBuilder::new(ids_map, module_name)
+    }
+
+    /// Fetches the names of a [`Localizer`] built by [`LocalizerBuilder::build`] from Wowhead
+    /// and writes them, the same way [`Localizer::run`] does.
+    pub fn execute(self) -> Result<RunReport, Error> {
+        let client = Self::default_client(&self.options)?;
+        let mut source = Wowhead::new(client, &self.options)?;
+        source.set_hosts(&self.hosts);
+
+        Ok(self.write_with_source(&source))
+    }
+
+    /// Same as [`Localizer::run`], but makes requests with the provided client
+    /// instead of building one from `options`.
+    pub fn run_with_client<P: Into<PathBuf>>(
+        client: HttpClient,
+        ids_map: Map<String, i64>,
+        module_name: &str,
+        output_dir: P,
+        options: Options,
+    ) -> Result<RunReport, Error> {
+        let source = Wowhead::new(client, &options)?;
+        Self::run_with_source(&source, ids_map, module_name, output_dir, options)
+    }
+
+    /// Same as [`Localizer::run`], but looks names up in `source` instead of Wowhead.
+    pub fn run_with_source<P: Into<PathBuf>>(
+        source: &dyn NameSource,
+        ids_map: Map<String, i64>,
+        module_name: &str,
+        output_dir: P,
+        options: Options,
+    ) -> Result<RunReport, Error> {
+        Self::check_locales(&options.locales)?;
+        let languages = Self::languages(module_name, &options.locales)
+            .into_iter()
+            .filter(|language| source.supports(language.1))
+            .map(|language| (language.1, language.2))
+            .collect();
+
+        let localizer = Self::new(
+            languages,
+            &ids_map,
+            module_name,
+            output_dir.into(),
+            Map::new(),
+            options,
+        );
+
+        Ok(localizer.write_with_source(source))
+    }
+
+    /// Works out what's left to fetch of every language, `languages` are `(code, header)`.
+    pub(crate) fn new(
+        languages: Vec<(&'static str, String)>,
+        ids_map: &Map<String, i64>,
+        module_name: &str,
+        output_dir: PathBuf,
+        hosts: Map<&'static str, String>,
+        options: Options,
+    ) -> Self {
+        let data = Self::construct_language_data(
+            languages,
+            ids_map,
+            if options.force_all && !options.only_invalid {
+                None
+            } else {
+                Some(&output_dir)
+            },
+            &options,
+        );
+        let order = options.input_order.then(|| {
+            ids_map
+                .keys()
+                .enumerate()
+                .map(|(i, name)| (name.clone(), i))
+                .collect()
+        });
+
+        Self {
+            data,
+            options,
+            module_name: module_name.to_string(),
             output_dir,
+            order,
+            hosts,
+        }
+    }
+
+    fn write_with_source(mut self, source: &dyn NameSource) -> RunReport {
+        let options = self.options.clone();
+        let output_dir = self.output_dir.clone();
+        let order = self.order.clone();
+
+        // Names an interrupted run fetched aren't fetched again, they're put back in their
+        // place before the file is written.
+        let checkpoint = (!options.dry_run)
+            .then(|| Checkpoint::open(&output_dir, &self.module_name, options.force_all));
+        let mut resumed = Map::new();
+        if let Some(checkpoint) = &checkpoint {
+            for language in &mut self.data {
+                let names = checkpoint.resumed(language.code, &language.ids_map);
+                if names.is_empty() {
+                    continue;
+                }
+                let positions: Map<_, _> = language
+                    .ids_map
+                    .keys()
+                    .enumerate()
+                    .map(|(i, name)| (name.clone(), i))
+                    .collect();
+                language.ids_map.retain(|name, _| !names.contains_key(name));
+                resumed.insert(language.code, (names, positions));
+            }
+        }
+        let resumed = Mutex::new(resumed);
+
+        let write_dir = options.staging_dir.as_ref().unwrap_or(&output_dir);
+        let tmp_dir = Self::get_tmp_dir(write_dir);
+        if !options.dry_run {
+            // Where temporary files go depends on the filesystems involved, which may've been
+            // different for an earlier run, so both places get cleaned up.
+            let removed = utils::remove_stale_tmp_files(&[&tmp_dir, write_dir]);
+            if removed > 0 {
+                log!("Removed {removed} temporary file(s) left behind by an earlier run.");
+            }
+        }
+        let dir = Self::open_dir(write_dir, &options);
+        let diffs = Mutex::new(Vec::new());
+        let files = Mutex::new(Vec::new());
+        let kept_existing = Mutex::new(Vec::new());
+        let report =
+            self.process_languages(source, checkpoint.as_ref(), |code, header, mut map| {
+                if let Some((names, positions)) = resumed.lock().unwrap().shift_remove(code) {
+                    map.extend(names);
+                    map.sort_by(|a, _, b, _| positions.get(a).cmp(&positions.get(b)));
+                }
+                let outcome = if options.dry_run {
+                    let (outcome, diff) =
+                        utils::diff_dir(&output_dir, code, header, map, order.as_ref(), &options)?;
+                    diffs.lock().unwrap().extend(diff.map(|diff| (code, diff)));
+                    outcome
+                } else {
+                    let outcome = utils::write_to_dir(
+                        &output_dir,
+                        &tmp_dir,
+                        code,
+                        header,
+                        map,
+                        order.as_ref(),
+                        &options,
+                    )?;
+                    Self::sync_written(dir.as_ref(), outcome.status);
+                    outcome
+                };
+                files.lock().unwrap().push((code, outcome.status));
+                kept_existing
+                    .lock()
+                    .unwrap()
+                    .extend(outcome.kept.into_iter().map(|kept| (code, kept)));
+                Ok(())
+            });
+
+        match report {
+            Some(mut report) => {
+                if options.dry_run {
+                    Self::print_diffs(diffs.into_inner().unwrap());
+                } else {
+                    Self::sync_dir(write_dir, &options);
+                }
+                report.files = files.into_inner().unwrap();
+                report.files.sort_by_key(|&(code, _)| code);
+                report.kept_existing = kept_existing.into_inner().unwrap();
+                report.kept_existing.sort_by_key(|(code, _)| *code);
+                report
+            }
+            None => RunReport::default(),
+        }
+    }
+
+    /// Fetches every name without touching any locale files, see [`Localizer::apply`].
+    pub fn fetch(
+        ids_map: Map<String, i64>,
+        module_name: &str,
+        options: Options,
+    ) -> Result<(Translations, RunReport), Error> {
+        Self::check_locales(&options.locales)?;
+        let source = Wowhead::from_options(&options)?;
+        Ok(Self::fetch_with_source(
+            &source,
+            ids_map,
+            module_name,
+            options,
+        ))
+    }
+
+    /// Same as [`Localizer::fetch`], but looks names up in `source` instead of Wowhead.
+    pub fn fetch_with_source(
+        source: &dyn NameSource,
+        ids_map: Map<String, i64>,
+        module_name: &str,
+        options: Options,
+    ) -> (Translations, RunReport) {
+        let localizer = Self {
+            data: Self::construct_language_data(
+                Self::languages(module_name, &options.locales)
+                    .into_iter()
+                    .filter(|language| source.supports(language.1))
+                    .map(|language| (language.1, language.2))
+                    .collect(),
+                &ids_map,
+                None,
+                &options,
+            ),
+            options,
+            module_name: module_name.to_string(),
+            output_dir: PathBuf::new(),
+            order: None,
+            hosts: Map::new(),
+        };
+
+        let locales = Mutex::new(Map::with_capacity(localizer.data.len()));
+        let report = localizer.process_languages(source, None, |code, _, map| {
+            let map = map
+                .into_iter()
+                .map(|(name, entry)| {
+                    if entry.is_valid {
+                        (name, entry.translation)
+                    } else {
+                        (name, format!("[{}]", entry.translation))
+                    }
+                })
+                .collect();
+            locales.lock().unwrap().insert(code.to_string(), map);
+            Ok(())
+        });
+
+        let mut locales = locales.into_inner().unwrap();
+        locales.sort_keys();
+
+        let translations = Translations {
+            module_name: Some(module_name.to_string()),
+            locales,
+            ..Default::default()
+        };
+
+        (translations, report.unwrap_or_default())
+    }
+
+    /// The `if L then ... end` block of `module_name` in the locale file of `code`, e.g. to be
+    /// pasted into a module by hand. Formats other than Lua get the whole file.
+    /// Files in [`Options::staging_dir`] take precedence over the ones in `output_dir`.
+    pub fn locale_block(
+        module_name: &str,
+        code: &str,
+        output_dir: &Path,
+        options: &Options,
+    ) -> io::Result<Option<String>> {
+        let path = match &options.staging_dir {
+            Some(staging_dir) if utils::locale_path(staging_dir, code, options).exists() => {
+                utils::locale_path(staging_dir, code, options)
+            }
+            _ => utils::locale_path(output_dir, code, options),
         };
+        let contents = fs::read_to_string(path)?;
+        if options.output_format != OutputKind::Lua {
+            return Ok(Some(contents));
+        }
+
+        let header = Self::default_languages(module_name)
+            .into_iter()
+            .find(|language| language.1 == code)
+            .map(|language| language.2);
 
-        localizer.process_languages();
+        Ok(header.and_then(|header| utils::extract_block(&contents, &header)))
+    }
+
+    /// Writes previously fetched names to the locale files in `output_dir`.
+    pub fn apply<P: Into<PathBuf>>(
+        translations: Translations,
+        module_name: &str,
+        output_dir: P,
+        options: Options,
+    ) -> RunReport {
+        let output_dir = output_dir.into();
+        let write_dir = options.staging_dir.as_ref().unwrap_or(&output_dir);
+        let tmp_dir = Self::get_tmp_dir(write_dir);
+        let dir = Self::open_dir(write_dir, &options);
+        // Whatever was fetched gets written, esMX included.
+        let codes: Vec<_> = translations.locales.keys().cloned().collect();
+        let languages = Self::languages(module_name, &codes);
+        let mut diffs = Vec::new();
+        let mut report = RunReport::default();
+
+        for (locale, names) in translations.locales {
+            let (code, header) = match languages.iter().find(|language| language.1 == locale) {
+                Some(language) => (language.1, &language.2),
+                None => {
+                    log!("Unknown locale \"{locale}\", skipping.");
+                    continue;
+                }
+            };
+
+            let order: Option<Map<_, _>> = options.input_order.then(|| {
+                names
+                    .keys()
+                    .enumerate()
+                    .map(|(i, name)| (name.clone(), i))
+                    .collect()
+            });
+
+            let map = names
+                .into_iter()
+                .map(|(name, translation)| {
+                    let raw = options.keep_raw_invalid.then(|| translation.clone());
+                    let (translation, is_valid) = utils::strip_invalid_marker(translation);
+                    let entry = Entry {
+                        translation,
+                        is_valid,
+                        id: None,
+                        raw,
+                    };
+                    (name, entry)
+                })
+                .collect();
+
+            let result = if options.dry_run {
+                utils::diff_dir(&output_dir, code, header, map, order.as_ref(), &options).map(
+                    |(outcome, diff)| {
+                        diffs.extend(diff.map(|diff| (code, diff)));
+                        outcome
+                    },
+                )
+            } else {
+                utils::write_to_dir(
+                    &output_dir,
+                    &tmp_dir,
+                    code,
+                    header,
+                    map,
+                    order.as_ref(),
+                    &options,
+                )
+            };
+            match result {
+                Ok(outcome) => {
+                    Self::sync_written(dir.as_ref(), outcome.status);
+                    report.processed.push(code);
+                    report.files.push((code, outcome.status));
+                    report
+                        .kept_existing
+                        .extend(outcome.kept.into_iter().map(|kept| (code, kept)));
+                }
+                Err((path, e)) => {
+                    log!("I/O error: {} ({})", e, path.to_string_lossy());
+                    report.failed += 1;
+                }
+            }
+        }
+
+        if options.dry_run {
+            Self::print_diffs(diffs);
+        } else {
+            Self::sync_dir(write_dir, &options);
+        }
+
+        report.processed.sort_unstable();
+        report.files.sort_by_key(|&(code, _)| code);
+        report
+    }
+
+    /// A single client is shared by every language, so connections are reused.
+    fn default_client(options: &Options) -> Result<HttpClient, Error> {
+        let mut builder = HttpClient::builder()
+            .timeout(Duration::from_secs(30))
+            .redirect_policy(RedirectPolicy::Limit(5));
+        if let Some(dial) = &options.dial {
+            let dialer: Dialer = dial
+                .parse()
+                .map_err(|_| format!("Invalid dial target \"{dial}\""))?;
+            builder = builder.dial(dialer);
+        }
+        if let Some(ca_cert) = &options.ca_cert {
+            builder = builder.ssl_ca_certificate(CaCertificate::file(ca_cert));
+        }
+        for (name, value) in Self::default_headers(options) {
+            builder = builder.default_header(name, value);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    fn default_headers(options: &Options) -> Vec<(&'static str, String)> {
+        let user_agent = match &options.user_agent {
+            Some(user_agent) => Cow::from(user_agent.as_str()),
+            None => default_user_agent(),
+        };
+
+        let mut headers = vec![
+            (
+                "accept",
+                String::from("text/html,application/xhtml+xml,application/xml;q=0.9"),
+            ),
+            ("accept-encoding", String::from("gzip, deflate")),
+            ("accept-language", String::from("en-US,en;q=0.9")),
+            ("sec-fetch-dest", String::from("document")),
+            ("sec-fetch-mode", String::from("navigate")),
+            ("sec-fetch-site", String::from("same-site")),
+            ("sec-fetch-user", String::from("?1")),
+            ("upgrade-insecure-requests", String::from("1")),
+            ("user-agent", user_agent.into_owned()),
+        ];
+        if let Some(identify) = &options.identify {
+            headers.push(("from", identify.clone()));
+        }
+
+        headers
+    }
+
+    /// Rewrites an existing locale file in the same style the tool writes them, without fetching anything.
+    /// Returns `false` if the file was already formatted.
+    pub fn format<P: AsRef<Path>>(
+        path: P,
+        options: &Options,
+    ) -> Result<bool, (PathBuf, io::Error)> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| (path.to_path_buf(), e))?;
+
+        match utils::canonicalize(&contents, options) {
+            Cow::Owned(formatted) if options.dry_run => {
+                print!("{}", utils::unified_diff(path, Some(&contents), &formatted));
+                Ok(true)
+            }
+            Cow::Owned(formatted) => {
+                let dir = match path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                let prefix = path.file_stem().unwrap_or_default().to_string_lossy();
+                let tmp_dir = Self::get_tmp_dir(dir);
+                if options.backup {
+                    utils::back_up(path)?;
+                }
+                utils::overwrite(&tmp_dir, path, &prefix, &formatted, options)?;
+                Self::sync_dir(dir, options);
+
+                Ok(true)
+            }
+            Cow::Borrowed(_) => Ok(false),
+        }
+    }
+
+    /// Serializes everything a run would use as TOML: the options along with
+    /// the headers of the default client and every language with its subdomain.
+    pub fn effective_config(module_name: &str, options: &Options) -> Result<String, Error> {
+        #[derive(Serialize)]
+        struct Language {
+            code: &'static str,
+            subdomain: String,
+            header: String,
+        }
+
+        #[derive(Serialize)]
+        struct Config<'a> {
+            module_name: &'a str,
+            options: &'a Options,
+            headers: Map<&'static str, String>,
+            languages: Vec<Language>,
+        }
+
+        let languages = Self::languages(module_name, &options.locales)
+            .into_iter()
+            .map(|language| Language {
+                code: language.1,
+                subdomain: Self::subdomain(&language, &options.locale_aliases),
+                header: language.2,
+            })
+            .collect();
+        let config = Config {
+            module_name,
+            options,
+            headers: Self::default_headers(options).into_iter().collect(),
+            languages,
+        };
+
+        Ok(toml::to_string(&config)?)
+    }
+
+    /// Looks up the name of a single entity in every language, or only in `locales` if it's
+    /// not empty. Nothing is read or written. Names considered invalid are put in brackets.
+    pub fn localize_one(
+        source: &dyn NameSource,
+        kind: EntityKind,
+        id: i64,
+        locales: &[String],
+        options: &Options,
+    ) -> Vec<(&'static str, Result<String, Error>)> {
+        Self::languages("", locales)
+            .into_par_iter()
+            .map(|language| language.1)
+            .filter(|code| source.supports(code))
+            .map(|code| {
+                let result = Self::fetch_name(source, kind, id, code, options).map(|name| {
+                    match Self::clean_name(name, code, options) {
+                        (name, true) => name,
+                        (name, false) => format!("[{name}]"),
+                    }
+                });
+                (code, result)
+            })
+            .collect()
+    }
+
+    /// Codes of every supported locale, e.g. `deDE`.
+    pub fn known_locales() -> Vec<&'static str> {
+        Self::default_languages("")
+            .iter()
+            .map(|language| language.1)
+            .collect()
+    }
+
+    /// Fails on the first code that isn't a known locale, listing the ones that are.
+    pub fn check_locales<S: AsRef<str>>(codes: &[S]) -> Result<(), Error> {
+        match codes
+            .iter()
+            .find(|code| !Self::is_known_locale(code.as_ref()))
+        {
+            Some(code) => Err(format!(
+                "Unknown locale code \"{}\", valid codes are {}",
+                code.as_ref(),
+                Self::known_locales().join(", ")
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks whether `code` is one of the locales this tool generates files for.
+    pub fn is_known_locale(code: &str) -> bool {
+        Self::default_languages("")
+            .iter()
+            .any(|language| language.1 == code)
+    }
+
+    /// The languages `locales` selects (see [`Options::locales`]). esES only falls back to esMX
+    /// when esMX doesn't get a file of its own.
+    pub(crate) fn languages(
+        module_name: &str,
+        locales: &[String],
+    ) -> Vec<(&'static str, &'static str, String)> {
+        let selected = |code: &str| {
+            if locales.is_empty() {
+                !OPTIONAL_LOCALES.contains(&code)
+            } else {
+                locales.iter().any(|locale| locale == code)
+            }
+        };
+        let separate_esmx = selected("esMX");
+
+        Self::default_languages(module_name)
+            .into_iter()
+            .filter(|language| selected(language.1))
+            .map(|mut language| {
+                if language.1 == "esES" && separate_esmx {
+                    language.2 = format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"esES\")");
+                }
+                language
+            })
+            .collect()
+    }
+
+    /// `(host, code, header)`, where the host is a Wowhead subdomain, a full host or a base URL,
+    /// see [`Localizer::subdomain`]. An empty host means there's no default source.
+    fn default_languages(module_name: &str) -> Vec<(&'static str, &'static str, String)> {
+        vec![
+            // ("www", "enUS", String::from("L = mod:GetLocale()")),
+            ("de", "deDE", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"deDE\")")),
+            ("es", "esES", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"esES\") or BigWigs:NewBossLocale(\"{module_name}\", \"esMX\")")),
+            // Wowhead has a single Spanish site, so unless it's aliased esMX gets the same names.
+            ("es", "esMX", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"esMX\")")),
+            ("fr", "frFR", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"frFR\")")),
+            ("it", "itIT", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"itIT\")")),
+            ("pt", "ptBR", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"ptBR\")")),
+            ("ru", "ruRU", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"ruRU\")")),
+            ("ko", "koKR", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"koKR\")")),
+            ("cn", "zhCN", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"zhCN\")")),
+            // Wowhead only has Simplified Chinese (`cn`), there's no Traditional Chinese site to
+            // scrape, so zhTW has no default host. It's fetched from whatever `--locale-alias zhTW=`
+            // points at (e.g. a mirror with Traditional names) or read from a CSV dump.
+            ("", "zhTW", format!("L = BigWigs:NewBossLocale(\"{module_name}\", \"zhTW\")")),
+        ]
+    }
+
+    /// Where a locale is fetched from: its `--locale-alias` if there's one, otherwise the default.
+    /// It's either a subdomain (`de`), a full host (`wow.example.com`) or a base URL
+    /// (`https://example.com/tw`).
+    fn subdomain(
+        language: &(&'static str, &'static str, String),
+        aliases: &Map<String, String>,
+    ) -> String {
+        aliases
+            .get(language.1)
+            .cloned()
+            .unwrap_or_else(|| language.0.to_string())
     }
 
     fn construct_language_data(
-        initial_data: Vec<(&'static str, &'static str, String)>,
+        initial_data: Vec<(&'static str, String)>,
         ids_map: &Map<String, i64>,
         output_dir: Option<&Path>,
+        options: &Options,
     ) -> Vec<LanguageData> {
+        let only_invalid = options.only_invalid;
         initial_data
             .into_par_iter()
-            .filter_map(|language| {
+            .filter_map(|(code, header)| {
                 let mut ids_map = ids_map.clone();
 
                 if let Some(output_dir) = output_dir {
-                    let file_path = output_dir.join(format!("{}.lua", language.1));
-                    if let Ok(file) = File::open(file_path) {
-                        let mut file = BufReader::new(file);
-                        let _ = utils::discard_existing(&mut file, &language.2, &mut ids_map);
+                    let file_path = utils::locale_path(output_dir, code, options);
+                    if let Ok(contents) = fs::read_to_string(file_path) {
+                        options.output_format.format().discard_existing(
+                            &contents,
+                            &header,
+                            &mut ids_map,
+                            only_invalid,
+                        );
+                    } else if only_invalid {
+                        ids_map.clear();
                     }
                 }
 
@@ -95,9 +1254,8 @@ impl Localizer {
                     None
                 } else {
                     Some(LanguageData {
-                        subdomain: language.0,
-                        code: language.1,
-                        header: language.2,
+                        code,
+                        header,
                         ids_map,
                     })
                 }
@@ -107,7 +1265,6 @@ impl Localizer {
 
     #[cfg(unix)]
     fn get_tmp_dir(output_dir: &Path) -> Cow<'_, Path> {
-        use std::fs;
         use std::os::unix::fs::MetadataExt;
 
         let os_tmp = env::temp_dir();
@@ -143,162 +1300,516 @@ impl Localizer {
         Cow::from(output_dir)
     }
 
-    fn process_languages(self) {
+    /// Prints diffs as one patch, ordered by locale.
+    fn print_diffs<S: Ord>(mut diffs: Vec<(S, String)>) {
+        diffs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut stdout = io::stdout().lock();
+        for (_, diff) in diffs {
+            let _ = stdout.write_all(diff.as_bytes());
+        }
+        let _ = stdout.flush();
+    }
+
+    /// The directory files are written to, for [`Localizer::sync_written`].
+    fn open_dir(dir: &Path, options: &Options) -> Option<File> {
+        if options.no_fsync || options.dry_run {
+            return None;
+        }
+        File::open(dir).ok()
+    }
+
+    /// Makes the rename of a file that was just written durable, so that a run that dies later on
+    /// doesn't lose it. A failure is left to the [`Localizer::sync_dir`] at the end to report.
+    fn sync_written(dir: Option<&File>, status: FileStatus) {
+        if let (Some(dir), FileStatus::Created | FileStatus::Updated) = (dir, status) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    fn sync_dir(output_dir: &Path, options: &Options) {
+        if options.no_fsync {
+            return;
+        }
+
+        if let Err(e) = File::open(output_dir).and_then(|dir| dir.sync_all()) {
+            log!(
+                "Failed to call fsync() on \"{}\": {}",
+                output_dir.display(),
+                e
+            );
+        }
+    }
+
+    /// Fetches a name, requesting it again if it's empty and `retry_on_empty` is set.
+    fn fetch_name(
+        source: &dyn NameSource,
+        kind: EntityKind,
+        id: i64,
+        code: &str,
+        options: &Options,
+    ) -> Result<String, Error> {
+        let mut result = source.fetch(kind, id, code);
+        if options.retry_on_empty {
+            for _ in 0..EMPTY_NAME_RETRIES {
+                match &result {
+                    Err(e) if e.is::<EmptyName>() => result = source.fetch(kind, id, code),
+                    _ => break,
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Drops the title suffix (e.g. ` <Quartermaster>`), the invalid marker and a configured
+    /// prefix, then truncates.
+    /// Returns the name and whether it's valid.
+    fn clean_name(translation: String, code: &str, options: &Options) -> (String, bool) {
         static TITLE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\s+<.+?>$"#).unwrap());
 
+        // Color codes go first, they may be wrapped around the title or the brackets.
+        let (translation, has_color_codes) = match options.color_codes {
+            ColorCodes::Keep => (translation, false),
+            _ => match utils::strip_color_codes(&translation) {
+                Some(stripped) => (stripped, true),
+                None => (translation, false),
+            },
+        };
+        let translation = utils::replace_owning(translation, &TITLE_REGEX, "");
+        let (translation, mut is_valid) = utils::strip_invalid_marker(translation);
+        if has_color_codes && options.color_codes == ColorCodes::Invalid {
+            is_valid = false;
+        }
+        let translation = match options.strip_prefixes.get(code) {
+            Some(prefixes) => utils::strip_prefix(translation, prefixes),
+            None => translation,
+        };
+        let translation = match options.truncate_names {
+            Some(max_len) => utils::truncate(translation, max_len),
+            None => translation,
+        };
+
+        (translation, is_valid)
+    }
+
+    /// Warns about IDs whose pages resolved to different IDs depending on the language,
+    /// e.g. because only some of them were redirected.
+    fn report_inconsistent_ids(canonical_ids: Map<(String, i64), Vec<(&'static str, i64)>>) {
+        for ((name, id), mut resolved) in canonical_ids {
+            if resolved
+                .iter()
+                .all(|&(_, canonical_id)| canonical_id == resolved[0].1)
+            {
+                continue;
+            }
+
+            resolved.sort_unstable();
+            let resolved = resolved
+                .iter()
+                .map(|(code, canonical_id)| format!("{code} -> {canonical_id}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            log!(
+                "WARNING: \"{name}\" ({id}) resolves to different IDs across languages: {resolved}"
+            );
+        }
+    }
+
+    /// Fetches names for every language and hands them over to `sink`, recording them in
+    /// `checkpoint` until they're handed over. Returns `None` if there was nothing to do.
+    fn process_languages<F>(
+        self,
+        source: &dyn NameSource,
+        checkpoint: Option<&Checkpoint>,
+        sink: F,
+    ) -> Option<RunReport>
+    where
+        F: Fn(&'static str, &str, Map<String, Entry>) -> Result<(), (PathBuf, io::Error)> + Sync,
+    {
         let total = self.data.iter().fold(0, |acc, el| acc + el.ids_map.len());
+        // The source may be shared by several runs, only this one's share is reported.
+        let cache_before = source.cache_stats();
 
-        if total > 0 {
+        // Languages with nothing left to fetch may still have resumed names to write.
+        if !self.data.is_empty() {
             let (tx, rx) = channel::bounded(total);
 
+            let silent = self.options.silent;
+            let quiet = self.options.quiet || silent;
+            // Tallies the same messages the progress line is drawn from.
             let stderr_thread = thread::spawn(move || {
                 let stderr = std::io::stderr();
                 let mut stderr = stderr.lock();
                 let mut processed = 0;
+                let mut tally = RunReport {
+                    total,
+                    ..Default::default()
+                };
 
-                let _ = write!(stderr, "\rProgress: 0 / {total}");
+                if !quiet {
+                    let _ = write!(stderr, "\rProgress: 0 / {total}");
+                }
                 while let Ok(msg) = rx.recv() {
-                    match msg {
+                    let message = match msg {
                         Err(ProcessingError::IoError((path, e))) => {
-                            let _ = writeln!(
-                                stderr,
-                                "\rI/O error: {} ({})",
-                                e,
-                                path.to_string_lossy(),
-                            );
+                            tally.failed += 1;
+                            Some(format!("I/O error: {} ({})", e, path.to_string_lossy()))
+                        }
+                        Err(ProcessingError::Warning(message)) => {
+                            tally.warnings.push(message.clone());
+                            Some(message)
                         }
                         Err(ProcessingError::DataError((language, mob_name, e))) => {
-                            let _ = writeln!(
-                                stderr,
-                                "\rFailed to collect data for \"{mob_name}\" ({language}), error: {e}"
+                            processed += 1;
+                            tally.failed += 1;
+                            let message = format!(
+                                "Failed to collect data for \"{mob_name}\" ({language}), error: {e}"
                             );
+                            tally.failures.push((language, mob_name, e.to_string()));
+                            Some(message)
+                        }
+                        _ => {
                             processed += 1;
+                            tally.succeeded += 1;
+                            None
+                        }
+                    };
+                    if let Some(message) = message.filter(|_| !silent) {
+                        if !logging::write_to_file(format_args!("{message}")) {
+                            let _ = writeln!(stderr, "\r{message}");
                         }
-                        _ => processed += 1,
                     }
-                    let _ = write!(stderr, "\rProgress: {processed} / {total}");
+                    if !quiet {
+                        let _ = write!(stderr, "\rProgress: {processed} / {total}");
+                    }
                 }
 
-                let _ = stderr.write(b"\n");
+                if !quiet {
+                    let _ = stderr.write(b"\n");
+                }
                 let _ = stderr.flush();
+
+                tally
             });
 
-            let output_dir = self.output_dir;
-            let tmp_dir = Self::get_tmp_dir(&output_dir);
-            self.data.into_par_iter().for_each({
-                |language| {
-                    let client = HttpClient::builder()
-                        .timeout(Duration::from_secs(30))
-                        .redirect_policy(RedirectPolicy::Limit(5))
-                        .default_header(
-                            "accept",
-                            "text/html,application/xhtml+xml,application/xml;q=0.9",
-                        )
-                        .default_header("accept-encoding", "gzip, deflate")
-                        .default_header("accept-language", "en-US,en;q=0.9")
-                        .default_header("sec-fetch-dest", "document")
-                        .default_header("sec-fetch-mode", "navigate")
-                        .default_header("sec-fetch-site", "same-site")
-                        .default_header("sec-fetch-user", "?1")
-                        .default_header("upgrade-insecure-requests", "1")
-                        .default_header("user-agent", &**USER_AGENT)
-                        .build()
-                        .unwrap();
-
-                    let code = language.code;
-                    let subdomain = language.subdomain;
-                    let map: Map<_, _> = language
-                        .ids_map
-                        .into_iter()
-                        .filter_map({
-                            let client = &client;
-                            let tx = tx.clone();
-
-                            move |(name, id)| {
-                                let result: Result<_, Error> = client
-                                    .get(&format!("https://{subdomain}.wowhead.com/npc={id}"))
-                                    .map_err(From::from)
-                                    .and_then(|mut response| {
-                                        Document::from_read(response.body_mut()).map_err(From::from)
-                                    })
-                                    .and_then(|document| {
-                                        document
-                                            .find(Class("heading-size-1"))
-                                            .next()
-                                            .ok_or_else(|| {
-                                                "Couldn't find an element .heading-size-1".into()
-                                            })
-                                            .and_then(|node| {
-                                                // Check if we were redirected to the search page.
-
-                                                if let Some(parent) = node.parent().and_then(|n| n.parent()) {
-                                                    if parent.is(Name("form")) {
-                                                        return Err("Not a valid NPC ID".into());
-                                                    }
-
-                                                    for child in parent.children() {
-                                                        if child.is(Class("database-detail-page-not-found-message")) {
-                                                            return Err("Not a valid NPC ID".into());
-                                                        }
-                                                    }
-                                                }
-
-                                                Ok(node.text())
-                                            })
-                                    });
-
-                                match result {
-                                    Ok(translation) => {
-                                        let _ = tx.send(Ok(()));
-                                        let translation =
-                                            utils::replace_owning(translation, &TITLE_REGEX, "");
-                                        let translation = if translation.contains('\"') {
-                                            translation.replace('\"', "\\\"")
-                                        } else {
-                                            translation
-                                        };
-                                        let (translation, is_valid) = match translation.as_bytes() {
-                                            [b'[', rest @ .., b']'] => {
-                                                (String::from_utf8(rest.to_vec()).unwrap(), false)
-                                            }
-                                            _ => (translation, true),
-                                        };
-                                        Some((name, (translation, is_valid)))
-                                    }
-                                    Err(e) => {
-                                        let _ = tx
-                                            .send(Err(ProcessingError::DataError((code, name, e))));
-                                        None
-                                    }
-                                }
+            let options = self.options;
+            let outage = AtomicBool::new(false);
+            let consecutive_outages = AtomicUsize::new(0);
+            let deadline = options.max_runtime.map(|limit| Instant::now() + limit);
+            let timed_out = AtomicBool::new(false);
+            let cancelled = AtomicBool::new(false);
+            let below_min_ratio = Mutex::new(Vec::new());
+            let processed = Mutex::new(Vec::new());
+            let canonical_ids = Mutex::new(Map::new());
+            let failures: Map<&'static str, AtomicUsize> = self
+                .data
+                .iter()
+                .map(|language| (language.code, AtomicUsize::new(0)))
+                .collect();
+            let is_aborted = |code: &str| {
+                options
+                    .max_failures_per_language
+                    .is_some_and(|max| failures[code].load(Ordering::Relaxed) >= max)
+            };
+
+            let mut data = self.data;
+            if !options.language_order.is_empty() {
+                data.sort_by_key(|language| {
+                    options
+                        .language_order
+                        .iter()
+                        .position(|code| code == language.code)
+                        .unwrap_or(usize::MAX)
+                });
+            }
+            let fetch_one = |code: &'static str, name: String, id: i64| {
+                if outage.load(Ordering::Relaxed) || is_aborted(code) {
+                    return None;
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    timed_out.store(true, Ordering::Relaxed);
+                    return None;
+                }
+                // Languages still get written with whatever they have by then.
+                if interrupt::is_cancelled() {
+                    cancelled.store(true, Ordering::Relaxed);
+                    return None;
+                }
+
+                let kind = options.kinds.get(&name).copied().unwrap_or_default();
+                let result = Self::fetch_name(source, kind, id, code, &options);
+                match &result {
+                    Err(e) if e.is::<SiteUnavailable>() => {
+                        let count = consecutive_outages.fetch_add(1, Ordering::Relaxed) + 1;
+                        if count >= OUTAGE_THRESHOLD {
+                            outage.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    _ => consecutive_outages.store(0, Ordering::Relaxed),
+                }
+
+                match result {
+                    Ok(translation) => {
+                        let _ = tx.send(Ok(()));
+                        if options.dedupe_across_languages {
+                            if let Some(canonical_id) = source.canonical_id(kind, id, code) {
+                                canonical_ids
+                                    .lock()
+                                    .unwrap()
+                                    .entry((name.clone(), id))
+                                    .or_insert_with(Vec::new)
+                                    .push((code, canonical_id));
                             }
-                        })
-                        .collect();
+                        }
+                        let raw = options
+                            .keep_raw_invalid
+                            .then(|| translation.trim().to_string());
+                        let (translation, mut is_valid) =
+                            Self::clean_name(translation, code, &options);
+                        let missing_script = options
+                            .script_check
+                            .filter(|_| is_valid)
+                            .zip(utils::missing_script(code, &translation));
+                        if let Some((check, script)) = missing_script {
+                            let _ = tx.send(Err(ProcessingError::Warning(format!(
+                                "WARNING: \"{name}\" ({code}) has no {script} characters, probably untranslated: \"{translation}\""
+                            ))));
+                            is_valid = check != ScriptCheck::Invalid;
+                        }
+                        if options.jsonl {
+                            emit_jsonl(serde_json::json!({
+                                "id": id,
+                                "locale": code,
+                                "name": translation,
+                                "status": if is_valid { "ok" } else { "invalid" },
+                            }));
+                        }
+                        let entry = Entry {
+                            translation,
+                            is_valid,
+                            id: Some(id),
+                            raw,
+                        };
+                        if let Some(Err(e)) =
+                            checkpoint.map(|checkpoint| checkpoint.record(code, &name, &entry))
+                        {
+                            let _ = tx.send(Err(ProcessingError::Warning(format!(
+                                "Failed to update the resume file: {e}"
+                            ))));
+                        }
+                        Some((name, entry))
+                    }
+                    Err(e) => {
+                        if options.jsonl {
+                            emit_jsonl(serde_json::json!({
+                                "id": id,
+                                "locale": code,
+                                "status": "error",
+                                "error": e.to_string(),
+                            }));
+                        }
+                        let _ = tx.send(Err(ProcessingError::DataError((code, name, e))));
+                        let count = failures[code].fetch_add(1, Ordering::Relaxed) + 1;
+                        if options.max_failures_per_language == Some(count) {
+                            let _ = tx.send(Err(ProcessingError::Warning(format!(
+                                "{code}: giving up after {count} failures, the rest of its names won't be fetched"
+                            ))));
+                        }
+                        None
+                    }
+                }
+            };
 
-                    if let Err(e) = utils::write_to_dir(
-                        &output_dir,
-                        &tmp_dir,
-                        language.code,
-                        &language.header,
-                        map,
-                    ) {
+            let finish = |language: LanguageData, map: Map<String, Entry>| {
+                if outage.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                let code = language.code;
+                if let Some(min_ratio) = options.min_translated_ratio {
+                    let ratio = map.len() as f64 / language.ids_map.len() as f64;
+                    if ratio < min_ratio {
+                        below_min_ratio.lock().unwrap().push((code, ratio));
+                        return;
+                    }
+                }
+
+                match sink(code, &language.header, map) {
+                    Ok(()) => {
+                        processed.lock().unwrap().push(code);
+                        if let Some(Err(e)) = checkpoint.map(|checkpoint| checkpoint.finish(code)) {
+                            let _ = tx.send(Err(ProcessingError::Warning(format!(
+                                "Failed to update the resume file: {e}"
+                            ))));
+                        }
+                    }
+                    Err(e) => {
                         let _ = tx.send(Err(ProcessingError::IoError(e)));
                     }
                 }
-            });
+            };
+
+            match options.schedule {
+                Schedule::ByLanguage => {
+                    // Unlike `into_par_iter`, which splits the list in halves, this hands
+                    // languages out in order as threads become free, so the first ones get
+                    // done first.
+                    data.into_iter().par_bridge().for_each(|language| {
+                        // Names of a language are fetched in parallel as well, the rate limiter
+                        // keeps the subdomain from being flooded. Collecting into a `Vec` keeps
+                        // the input order.
+                        let names: Vec<_> = language
+                            .ids_map
+                            .iter()
+                            .map(|(name, &id)| (name.clone(), id))
+                            .collect();
+                        let entries: Vec<_> = names
+                            .into_par_iter()
+                            .filter_map(|(name, id)| fetch_one(language.code, name, id))
+                            .collect();
+                        finish(language, entries.into_iter().collect());
+                    });
+                }
+                Schedule::ByNpc => {
+                    // Every NPC once, along with the languages that still need it.
+                    let mut npcs: Map<(String, i64), Vec<&'static str>> = Map::new();
+                    for language in &data {
+                        for (name, &id) in &language.ids_map {
+                            npcs.entry((name.clone(), id))
+                                .or_default()
+                                .push(language.code);
+                        }
+                    }
+
+                    let fetched = Mutex::new(Map::<&'static str, Map<String, Entry>>::new());
+                    npcs.into_iter()
+                        .par_bridge()
+                        .for_each(|((name, id), codes)| {
+                            codes.into_par_iter().for_each(|code| {
+                                if let Some((name, entry)) = fetch_one(code, name.clone(), id) {
+                                    fetched
+                                        .lock()
+                                        .unwrap()
+                                        .entry(code)
+                                        .or_default()
+                                        .insert(name, entry);
+                                }
+                            });
+                        });
+
+                    // Names go back into the order `by-language` would've fetched them in,
+                    // so that both write the same files.
+                    let mut fetched = fetched.into_inner().unwrap();
+                    data.into_iter()
+                        .map(|language| {
+                            let mut names = fetched.remove(language.code).unwrap_or_default();
+                            let map = language
+                                .ids_map
+                                .keys()
+                                .filter_map(|name| {
+                                    let entry = names.remove(name)?;
+                                    Some((name.clone(), entry))
+                                })
+                                .collect();
+                            (language, map)
+                        })
+                        .par_bridge()
+                        .for_each(|(language, map)| finish(language, map));
+                }
+            }
 
             drop(tx);
-            stderr_thread.join().unwrap();
-
-            if let Err(e) = File::open(&output_dir).and_then(|dir| dir.sync_all()) {
-                eprintln!(
-                    "Failed to call fsync() on \"{}\": {}",
-                    output_dir.display(),
-                    e
-                );
+            let tally = stderr_thread.join().unwrap();
+
+            // Whatever wasn't written is kept for the next run.
+            if let Some(Err(e)) = checkpoint.map(Checkpoint::write) {
+                log!("Failed to write the resume file: {}", e);
             }
+
+            if outage.into_inner() {
+                log!("Wowhead appears to be down, try later.");
+            }
+            let timed_out = timed_out.into_inner();
+            if timed_out {
+                log!("Ran out of time, the remaining names weren't fetched.");
+            }
+            let cancelled = cancelled.into_inner();
+            if cancelled {
+                log!("Interrupted, the remaining names weren't fetched.");
+            }
+            if options.dedupe_across_languages {
+                Self::report_inconsistent_ids(canonical_ids.into_inner().unwrap());
+            }
+
+            let mut aborted: Vec<_> = failures
+                .keys()
+                .copied()
+                .filter(|&code| is_aborted(code))
+                .collect();
+            aborted.sort_unstable();
+            let mut below_min_ratio = below_min_ratio.into_inner().unwrap();
+            below_min_ratio.sort_by_key(|&(code, _)| code);
+            let mut processed = processed.into_inner().unwrap();
+            processed.sort_unstable();
+
+            Some(RunReport {
+                processed,
+                aborted,
+                below_min_ratio,
+                timed_out,
+                cancelled,
+                cache: source
+                    .cache_stats()
+                    .zip(cache_before)
+                    .map(|(after, before)| after.since(before)),
+                ..tally
+            })
         } else {
-            eprintln!("There's nothing to do.");
+            log!("There's nothing to do.");
+
+            None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_lets_a_second_worth_of_requests_through_at_once() {
+        let limiter = RateLimiter::new(10.0);
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            limiter.acquire("de.wowhead.com");
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // The bucket is empty, the next request waits for a token.
+        limiter.acquire("de.wowhead.com");
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        // Other hosts have buckets of their own.
+        let start = Instant::now();
+        limiter.acquire("fr.wowhead.com");
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn rate_limiter_keeps_fractions_of_a_token() {
+        let limiter = RateLimiter::new(2.5);
+
+        let start = Instant::now();
+        limiter.acquire("de.wowhead.com");
+        limiter.acquire("de.wowhead.com");
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // Half a token is left, the other half takes 200ms.
+        limiter.acquire("de.wowhead.com");
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(190));
+        assert!(elapsed < Duration::from_millis(390));
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+static LOG_FILE: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+
+/// Sends warnings and errors to `path` instead of stderr from now on, appending to the file.
+/// The progress line stays on stderr.
+pub fn log_to_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG_FILE.lock().unwrap() = Some(LineWriter::new(file));
+
+    Ok(())
+}
+
+/// Writes a line to the log file, returns `false` if there's none.
+pub(crate) fn write_to_file(args: fmt::Arguments) -> bool {
+    match LOG_FILE.lock().unwrap().as_mut() {
+        Some(file) => {
+            let _ = writeln!(file, "{args}");
+            let _ = file.flush();
+            true
+        }
+        None => false,
+    }
+}
+
+#[doc(hidden)]
+pub fn write(args: fmt::Arguments) {
+    if !write_to_file(args) {
+        eprintln!("{args}");
+    }
+}
+
+/// Same as `eprintln!`, unless [`log_to_file`] was called.
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logging::write(format_args!($($arg)*))
+    };
+}
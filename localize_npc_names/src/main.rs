@@ -1,85 +1,1268 @@
 use indexmap::IndexMap as Map;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
     env,
-    fs::{self, File},
-    io::{BufReader, Seek, SeekFrom},
-    path::PathBuf,
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
-use localize_npc_names::{Error, Localizer};
+use arboard::Clipboard;
+use git2::{Repository, Status, StatusOptions};
+use isahc::config::Dialer;
+use localize_npc_names::{
+    install_interrupt_handler, log, ColorCodes, CsvDump, EmbeddedData, EntityKind, Error,
+    GameVersion, Localizer, NameSource, OnInvalid, Options, OutputKind, RunReport, Schedule,
+    ScriptCheck, Translations, Wowhead,
+};
+use onig::Regex;
 
 #[cfg(all(target_env = "musl", target_pointer_width = "64"))]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+/// Kept in the output directory by `--skip-unchanged`.
+const MANIFEST_NAME: &str = ".localize_npc_names.toml";
+
+/// Inputs of the last successful runs, keyed by their canonical paths.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    inputs: Map<String, ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    hash: String,
+    /// Seconds since the Unix epoch.
+    last_run: u64,
+}
+
+/// Hashes of the locale files a run left behind, keyed by file name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    files: Map<String, String>,
+}
+
 #[derive(Debug, Deserialize)]
+struct MultiModuleInput {
+    module: Vec<InputFile>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct InputFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
     module_name: Option<String>,
     npcs: Map<String, i64>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    achievements: Map<String, i64>,
+}
+
+enum Command {
+    Run,
+    Fetch,
+    Apply,
+    Format,
+    Names,
+    Merge,
+}
+
+#[derive(Clone, Copy)]
+enum IndexFormat {
+    List,
+    Xml,
+    Toc,
+}
+
+fn print_usage_and_exit(program_name: &OsStr) -> ! {
+    eprintln!(
+        "Usage: {0} [OPTIONS] <YAML FILE> <OUTPUT DIR> [MODULE NAME]
+       {0} [OPTIONS] fetch <YAML FILE> <TOML|JSON FILE> [MODULE NAME]
+       {0} apply <TOML|JSON FILE> <OUTPUT DIR> [MODULE NAME]
+       {0} format <LUA FILE>...
+       {0} [OPTIONS] names <ID>...
+       {0} merge <INPUT FILE> <INPUT FILE>...
+
+Options:
+    --identify \"tool (contact)\"      Send a From header identifying the tool
+    --truncate-names <N>             Truncate names longer than N bytes
+    --locale-alias <CODE=SUBDOMAIN>  Fetch CODE from another Wowhead subdomain, can be repeated
+                                     (or a full host or base URL, which zhTW needs)
+    --module-name-for <FILE=NAME>    Use NAME as the module name when the input is FILE, can be repeated
+    --emit-index <PATH>              List the locale files in OUTPUT DIR after a run
+    --index-format <list|xml|toc>    Format of the index, defaults to list
+    --snapshot <PATH>                Store hashes of the locale files after a run
+    --verify-snapshot <PATH>         Fail if the locale files differ from a stored snapshot
+    --annotate-ids                   Append NPC IDs as comments to written lines
+    --input-order                    Order written lines the same way as the input
+    --sort-names                     Order written lines by variable name
+    --min-translated-ratio <RATIO>   Fail without writing a language if fewer names were fetched
+    --max-failures-per-language <N>  Stop fetching a language after N failures
+    --max-retries <N>                Retry network errors and 5xx responses N times (default: 3)
+    --requests-per-second <N>        Make at most N requests a second to each subdomain (default: 5)
+    --max-inflight <N>               Have at most N requests in flight at once (default: 4 per language)
+    --cache-ttl <DURATION>           Fetch names cached by earlier runs again after e.g. 1h (default: 7d)
+    --retry-on-empty                 Request a page again if the name on it is empty
+    --no-fsync                       Don't wait for files to hit the disk, unsafe if the system crashes
+    --backup                         Copy locale files to e.g. deDE.lua.bak before changing them
+    --dry-run                        Print a unified diff of the changes instead of writing them
+    --invalid-prefix <PREFIX>        Comment out invalid names with PREFIX instead of \"-- \"
+    --indent <tab|N>                 Indent new files with a tab or N spaces (default: tab)
+    --har <PATH>                     Record every request and response into an HTTP Archive
+    --jsonl                          Print every result to stdout as a line of JSON
+    --report <PATH>                  Write a summary of the run as JSON
+    --max-runtime <DURATION>         Stop making requests after e.g. 300s or 5m and fail
+    --timeouts <DURATION,...>        Retry timed out requests with each of e.g. 5s,15s,30s in turn
+    --print-config                   Print the effective configuration as TOML instead of running
+    --csv-dir <DIR>                  Look names up in CSV exports of client tables instead of Wowhead
+    --only-invalid                   Only refetch names that are commented out as invalid
+    --log-file <PATH>                Append warnings and errors to PATH instead of printing them
+    --quiet                          Don't print the progress line
+    --dedupe-across-languages        Warn about IDs that redirect differently depending on the language
+    --only <CODE,...>                Only look up these languages with names
+    --prefer-existing                Keep names in the locale files that differ from fetched ones
+    --append-only                    Only add new names, never change or move existing lines
+    --preamble <FILE>                Start every locale file with the contents of FILE
+    --epilogue <FILE>                End every locale file with the contents of FILE
+    --coverage-comment               Start every locale file with how many names are localized
+    --skip-unchanged                 Do nothing if the input is the same as in the last successful run
+    --clipboard <LANG>               Copy the locale block of LANG (e.g. de) to the clipboard
+    --locales <CODE,...>             Only process these locales, esMX gets its own file if listed
+    --language-order <LANG,...>      Process these languages first, e.g. de,ru,zh
+    --strip-prefix <CODE=PREFIX>     Remove PREFIX from the start of CODE names, can be repeated
+    --game-version <VERSION>         retail (default), classic, cata or sod: database to fetch from
+    --base-url <URL>                 Fetch from a mirror, e.g. http://{{subdomain}}.wowhead.local
+    --dial <unix:PATH|tcp:ADDR>      Connect to a Unix socket or another address instead
+    --ca-cert <PATH>                 Trust the CA certificates in a PEM file instead of the system's
+    --follow-canonical <REGEX>       Follow the canonical link of pages whose name matches REGEX
+    --embedded-data <first|fallback> Use names embedded in page data first or as a fallback
+    --color-codes <POLICY>           keep (default), strip or invalid: handling of |c...|r in names
+    --warn-on-ascii                  Warn about ruRU, koKR and zhCN names written in another script
+    --ascii-as-invalid               Same, but also comment such names out
+    --keep-raw-invalid               Append invalid names as they were fetched as a comment
+    --name-selectors <CLASS,...>     Look for names in these elements, the first is the heading
+    --on-invalid <POLICY>            comment (default), skip or keep: how invalid names are written
+    --no-comment-invalid             Same as --on-invalid skip
+    --output-format <lua|json|toml>  Format of the locale files, defaults to lua
+    --schedule <by-language|by-npc>  Fetch a language or an NPC at a time, defaults to by-language
+    --require-clean-git              Refuse to write unless OUTPUT DIR is committed to git
+    --key-by-id                      Key names in fetch dumps by ID instead of by variable
+    --output-to-staging <DIR>        Write locale files to DIR instead of overwriting OUTPUT DIR",
+        program_name.to_string_lossy()
+    );
+    std::process::exit(1);
+}
+
+/// Either a full code or the language part of it, e.g. `de` for `deDE`.
+fn resolve_language(value: &str) -> Option<&'static str> {
+    Localizer::known_locales()
+        .into_iter()
+        .find(|code| *code == value || code.get(..2) == Some(value))
+}
+
+/// The module name given to `input_path` with `--module-name-for`, if any.
+/// Paths that can't be canonicalized are compared as they are.
+fn module_name_for(module_names: &Map<PathBuf, String>, input_path: &Path) -> Option<String> {
+    let canonical_input = fs::canonicalize(input_path).ok();
+    module_names
+        .iter()
+        .find(
+            |(file, _)| match (&canonical_input, fs::canonicalize(file)) {
+                (Some(input), Ok(file)) => *input == file,
+                _ => file.as_path() == input_path,
+            },
+        )
+        .map(|(_, module_name)| module_name.clone())
+}
+
+/// Parses durations like `300`, `300s`, `5m`, `1h` or `7d`, plain numbers are seconds.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, multiplier) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1),
+        (i, 'm') => (&value[..i], 60),
+        (i, 'h') => (&value[..i], 60 * 60),
+        (i, 'd') => (&value[..i], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .map(Duration::from_secs)
+}
+
+fn fallback_module_name(path: &Path) -> String {
+    format!("{} Trash", &path.file_stem().unwrap().to_string_lossy())
+}
+
+/// Dumps of fetched names are JSON if their extension says so, TOML otherwise.
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+/// Both YAML and TOML use `#` for comments.
+fn is_effectively_empty(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(str::trim)
+        .all(|line| line.is_empty() || line.starts_with('#'))
+}
+
+/// IDs keyed by variable name and the module's name.
+type Module = (Map<String, i64>, String);
+
+fn resolve_module(
+    yaml_path: &Path,
+    mut input: InputFile,
+    module_name: Option<String>,
+    kinds: &mut Map<String, EntityKind>,
+) -> Module {
+    let module_name = match (module_name, input.module_name) {
+        (Some(inner), _) => inner,
+        (_, Some(inner)) => inner,
+        _ => {
+            log!("WARNING: module_name is missing, falling back to using the file's name");
+            fallback_module_name(yaml_path)
+        }
+    };
+    for (name, id) in input.achievements {
+        kinds.insert(name.clone(), EntityKind::Achievement);
+        input.npcs.insert(name, id);
+    }
+
+    (input.npcs, module_name)
+}
+
+/// Returns every module in the input along with its name.
+/// Achievements end up in the same map as NPCs, `kinds` tells them apart.
+fn read_input(
+    yaml_path: &Path,
+    contents: &str,
+    module_name: Option<String>,
+    kinds: &mut Map<String, EntityKind>,
+) -> Result<Vec<Module>, Error> {
+    if let Ok(input) = serde_yaml::from_str::<MultiModuleInput>(contents) {
+        if input.module.is_empty() {
+            return Err(format!("\"{}\" has an empty module list", yaml_path.display()).into());
+        }
+        if module_name.is_some() {
+            log!("WARNING: the input has several modules, ignoring the given module name");
+        }
+        return Ok(input
+            .module
+            .into_iter()
+            .map(|input| resolve_module(yaml_path, input, None, kinds))
+            .collect());
+    }
+
+    match serde_yaml::from_str::<InputFile>(contents) {
+        Ok(input) => Ok(vec![resolve_module(yaml_path, input, module_name, kinds)]),
+        Err(_) => {
+            let ids_map = serde_yaml::from_str(contents)?;
+            let module_name = match module_name {
+                Some(module_name) => module_name,
+                None => fallback_module_name(yaml_path),
+            };
+            Ok(vec![(ids_map, module_name)])
+        }
+    }
+}
+
+/// 64-bit FNV-1a, unlike `DefaultHasher` it's guaranteed to stay the same between releases.
+fn content_hash(contents: &str) -> String {
+    let hash = contents.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+
+    format!("{hash:016x}")
+}
+
+fn manifest_key(input_path: &Path) -> String {
+    fs::canonicalize(input_path)
+        .unwrap_or_else(|_| input_path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A missing or broken manifest is treated as an empty one.
+fn read_manifest(output_dir: &Path) -> Manifest {
+    let path = output_dir.join(MANIFEST_NAME);
+    match fs::read_to_string(&path) {
+        Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+            log!("WARNING: ignoring \"{}\": {}", path.display(), e);
+            Manifest::default()
+        }),
+        Err(_) => Manifest::default(),
+    }
+}
+
+fn write_manifest(output_dir: &Path, manifest: &Manifest) -> Result<(), Error> {
+    fs::write(output_dir.join(MANIFEST_NAME), toml::to_string(manifest)?)?;
+    Ok(())
+}
+
+/// Fails unless `output_dir` is inside a git working tree and has no uncommitted changes
+/// (untracked files included) that overwriting it would mix with the regenerated names.
+fn ensure_clean_git(output_dir: &Path) -> Result<(), Error> {
+    let output_dir = fs::canonicalize(output_dir)?;
+    let repo = Repository::discover(&output_dir).map_err(|_| {
+        format!(
+            "\"{}\" isn't inside a git working tree",
+            output_dir.display()
+        )
+    })?;
+    let workdir = repo
+        .workdir()
+        .ok_or("the git repository doesn't have a working tree")?;
+    let workdir = fs::canonicalize(workdir)?;
+    let pathspec = output_dir
+        .strip_prefix(&workdir)
+        .unwrap_or(&output_dir)
+        .to_string_lossy()
+        .replace('\\', "/");
+
+    let mut status_options = StatusOptions::new();
+    status_options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+    if !pathspec.is_empty() {
+        status_options.pathspec(&pathspec);
+    }
+    let dirty: Vec<_> = repo
+        .statuses(Some(&mut status_options))?
+        .iter()
+        .filter(|entry| entry.status() != Status::CURRENT)
+        .filter_map(|entry| entry.path().map(String::from))
+        .collect();
+
+    if dirty.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "\"{}\" has uncommitted changes, commit or stash them first: {}",
+            output_dir.display(),
+            dirty.join(", ")
+        )
+        .into())
+    }
+}
+
+/// Every locale file with the given extension in `dir`, sorted.
+fn locale_files(dir: &Path, extension: &str) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_locale_file = path.extension().is_some_and(|ext| ext == extension)
+            && path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(Localizer::is_known_locale);
+
+        if is_locale_file {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Writes the names of all locale files in `output_dir`, relative to the index's directory.
+fn write_index(index_path: &Path, output_dir: &Path, format: IndexFormat) -> io::Result<()> {
+    let files = locale_files(output_dir, "lua")?;
+
+    let base_dir = index_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut index = String::new();
+    if let IndexFormat::Xml = format {
+        index.push_str("<Ui xmlns=\"http://www.blizzard.com/wow/ui/\">\n");
+    }
+    for path in files {
+        let path = path.strip_prefix(base_dir).unwrap_or(&path);
+        match format {
+            IndexFormat::List => {
+                index.push_str(&path.to_string_lossy());
+                index.push('\n');
+            }
+            IndexFormat::Xml | IndexFormat::Toc => {
+                // The game expects backslashes regardless of the platform.
+                let path = path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("\\");
+
+                if let IndexFormat::Xml = format {
+                    index.push_str(&format!("\t<Script file=\"{path}\"/>\n"));
+                } else {
+                    index.push_str(&path);
+                    index.push('\n');
+                }
+            }
+        }
+    }
+    if let IndexFormat::Xml = format {
+        index.push_str("</Ui>\n");
+    }
+
+    fs::write(index_path, index)
+}
+
+fn take_snapshot(dir: &Path, extension: &str) -> io::Result<Snapshot> {
+    let mut snapshot = Snapshot::default();
+    for path in locale_files(dir, extension)? {
+        let contents = fs::read_to_string(&path)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        snapshot
+            .files
+            .insert(name.into_owned(), content_hash(&contents));
+    }
+
+    Ok(snapshot)
+}
+
+/// Reports every file that differs from `expected`, returns whether there were any.
+fn compare_snapshots(expected: &Snapshot, actual: &Snapshot) -> bool {
+    let mut differs = false;
+    for (name, hash) in &expected.files {
+        match actual.files.get(name) {
+            Some(actual_hash) if actual_hash == hash => (),
+            Some(_) => {
+                log!("Snapshot mismatch: \"{name}\" changed");
+                differs = true;
+            }
+            None => {
+                log!("Snapshot mismatch: \"{name}\" is missing");
+                differs = true;
+            }
+        }
+    }
+    for name in actual.files.keys() {
+        if !expected.files.contains_key(name) {
+            log!("Snapshot mismatch: \"{name}\" wasn't expected");
+            differs = true;
+        }
+    }
+
+    differs
+}
+
+fn print_effective_config(module_name: &str, options: &Options) -> Result<(), Error> {
+    print!("{}", Localizer::effective_config(module_name, options)?);
+    Ok(())
+}
+
+fn format_files(paths: &[PathBuf], options: &Options) -> Result<(), Error> {
+    let mut failed = false;
+    for path in paths {
+        match Localizer::format(path, options) {
+            Ok(true) if !options.dry_run => log!("Formatted \"{}\"", path.display()),
+            Ok(_) => (),
+            Err((path, e)) => {
+                log!("I/O error: {} ({})", e, path.display());
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Copies the locale blocks of `code` to the clipboard. Where there's no clipboard
+/// (e.g. on a headless system) they're printed to stdout instead.
+fn copy_to_clipboard(module_names: &[String], code: &str, output_dir: &Path, options: &Options) {
+    let mut blocks = Vec::with_capacity(module_names.len());
+    for module_name in module_names {
+        match Localizer::locale_block(module_name, code, output_dir, options) {
+            // Formats other than Lua give the whole file, which is the same for every module.
+            Ok(Some(block)) if !blocks.contains(&block) => blocks.push(block),
+            Ok(Some(_)) => (),
+            Ok(None) => log!("There's no {code} block for \"{module_name}\" to copy"),
+            Err(e) => log!("Couldn't read the {code} locale file: {e}"),
+        }
+    }
+    if blocks.is_empty() {
+        return;
+    }
+
+    let text = blocks.join("\n");
+    let result = Clipboard::new().and_then(|mut clipboard| {
+        #[cfg(target_os = "linux")]
+        {
+            use arboard::SetExtLinux;
+
+            // Contents of X11 and Wayland clipboards are gone once whoever set them exits,
+            // unless a clipboard manager takes them over.
+            log!("{code}: copying to the clipboard, it stays there until something else is copied");
+            clipboard.set().wait().text(text.as_str())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            clipboard.set_text(text.as_str())?;
+            log!("{code}: copied to the clipboard");
+            Ok(())
+        }
+    });
+    if let Err(e) = result {
+        log!("Couldn't access the clipboard ({e}), printing the {code} block instead");
+        print!("{text}");
+    }
+}
+
+/// Adds `ids` to `merged` unless the variable or the ID is already there, returns how many
+/// of them conflicted with what's there.
+fn merge_ids(
+    path: &Path,
+    section: &str,
+    ids: Map<String, i64>,
+    merged: &mut Map<String, i64>,
+) -> usize {
+    let mut conflicts = 0;
+    for (name, id) in ids {
+        if let Some(&existing) = merged.get(&name) {
+            if existing != id {
+                log!(
+                    "{section}.{name} is {existing}, but {id} in \"{}\", keeping {existing}",
+                    path.display()
+                );
+                conflicts += 1;
+            }
+            continue;
+        }
+        if let Some((other, _)) = merged.iter().find(|(_, &existing)| existing == id) {
+            log!(
+                "{id} is {section}.{other}, but {section}.{name} in \"{}\", keeping {other}",
+                path.display()
+            );
+            conflicts += 1;
+            continue;
+        }
+        merged.insert(name, id);
+    }
+
+    conflicts
+}
+
+/// Combines several input files into one, printed to stdout in the format of the first file.
+/// The first file wins conflicts, which are reported, and the exit code is nonzero then.
+fn merge_inputs(paths: &[PathBuf]) -> Result<(), Error> {
+    let is_toml = paths[0].extension().is_some_and(|ext| ext == "toml");
+
+    let mut merged = InputFile::default();
+    let mut conflicts = 0;
+    for path in paths {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read \"{}\": {}", path.display(), e))?;
+        let input: InputFile = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            serde_yaml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?
+        };
+
+        match (&merged.module_name, input.module_name) {
+            (None, module_name) => merged.module_name = module_name,
+            (Some(existing), Some(module_name)) if *existing != module_name => {
+                log!(
+                    "module_name is \"{existing}\", but \"{module_name}\" in \"{}\", keeping \"{existing}\"",
+                    path.display()
+                );
+                conflicts += 1;
+            }
+            _ => (),
+        }
+        conflicts += merge_ids(path, "npcs", input.npcs, &mut merged.npcs);
+        conflicts += merge_ids(
+            path,
+            "achievements",
+            input.achievements,
+            &mut merged.achievements,
+        );
+    }
+
+    if is_toml {
+        print!("{}", toml::to_string(&merged)?);
+    } else {
+        print!("{}", serde_yaml::to_string(&merged)?);
+    }
+
+    if conflicts > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Prints a table with a row per ID and a column per language.
+fn print_names(
+    ids: &[i64],
+    locales: &[String],
+    csv_dir: Option<&Path>,
+    options: &Options,
+) -> Result<(), Error> {
+    let source: Box<dyn NameSource> = match csv_dir {
+        Some(csv_dir) => Box::new(CsvDump::open(csv_dir)?),
+        None => Box::new(Wowhead::from_options(options)?),
+    };
+
+    let mut header_printed = false;
+    for &id in ids {
+        let names = Localizer::localize_one(source.as_ref(), EntityKind::Npc, id, locales, options);
+        if !header_printed {
+            let codes: Vec<_> = names.iter().map(|(code, _)| *code).collect();
+            println!("id | {}", codes.join(" | "));
+            header_printed = true;
+        }
+
+        let cells: Vec<_> = names
+            .into_iter()
+            .map(|(code, result)| match result {
+                Ok(name) => name,
+                Err(e) => {
+                    log!("Failed to collect data for {id} ({code}), error: {e}");
+                    String::from("(error)")
+                }
+            })
+            .collect();
+        println!("{id} | {}", cells.join(" | "));
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<(), Error> {
-    let (yaml_path, output_dir, module_name) = {
+    let mut options = Options::default();
+    let mut index_path = None;
+    let mut snapshot_path = None;
+    let mut verify_snapshot_path = None;
+    let mut report_path = None;
+    let mut index_format = IndexFormat::List;
+    let mut module_names = Map::new();
+    let mut print_config = false;
+    let mut csv_dir = None;
+    let mut only = Vec::new();
+    let mut skip_unchanged = false;
+    let mut require_clean_git = false;
+    let mut key_by_id = false;
+    let mut clipboard = None;
+    let (command, input_path, output_path, module_name) = {
         let mut args = env::args_os();
         let program_name = args.next().unwrap();
 
+        let mut positional = Vec::with_capacity(4);
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--identify") => match args.next() {
+                    Some(value) => options.identify = Some(value.to_string_lossy().into_owned()),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--truncate-names") => {
+                    match args.next().and_then(|v| v.to_str()?.parse().ok()) {
+                        Some(value) => options.truncate_names = Some(value),
+                        None => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--module-name-for") => {
+                    let rule = args.next();
+                    match rule.as_ref().and_then(|v| v.to_str()?.split_once('=')) {
+                        Some((file, module_name))
+                            if !file.is_empty() && !module_name.is_empty() =>
+                        {
+                            module_names.insert(PathBuf::from(file), module_name.to_string());
+                        }
+                        _ => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--locale-alias") => {
+                    let alias = args.next();
+                    match alias.as_ref().and_then(|v| v.to_str()?.split_once('=')) {
+                        Some((code, subdomain)) => {
+                            if !Localizer::is_known_locale(code) {
+                                eprintln!("Unknown locale code \"{code}\" in --locale-alias");
+                                std::process::exit(1);
+                            }
+                            options
+                                .locale_aliases
+                                .insert(code.to_string(), subdomain.to_string());
+                        }
+                        None => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--strip-prefix") => {
+                    let rule = args.next();
+                    match rule.as_ref().and_then(|v| v.to_str()?.split_once('=')) {
+                        Some((code, prefix)) if !prefix.is_empty() => {
+                            if !Localizer::is_known_locale(code) {
+                                eprintln!("Unknown locale code \"{code}\" in --strip-prefix");
+                                std::process::exit(1);
+                            }
+                            options
+                                .strip_prefixes
+                                .entry(code.to_string())
+                                .or_default()
+                                .push(prefix.to_string());
+                        }
+                        _ => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--base-url") => match args.next() {
+                    Some(value) => options.base_url = Some(value.to_string_lossy().into_owned()),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--dial") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(value) if value.parse::<Dialer>().is_ok() => {
+                        options.dial = Some(value.to_string())
+                    }
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--ca-cert") => match args.next() {
+                    Some(value) => {
+                        let path = PathBuf::from(value);
+                        if !path.is_file() {
+                            eprintln!("\"{}\" is not a file", path.display());
+                            std::process::exit(1);
+                        }
+                        options.ca_cert = Some(path);
+                    }
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--follow-canonical") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(value) if Regex::new(value).is_ok() => {
+                        options.follow_canonical = Some(value.to_string())
+                    }
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--embedded-data") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("first") => options.embedded_data = Some(EmbeddedData::First),
+                    Some("fallback") => options.embedded_data = Some(EmbeddedData::Fallback),
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--color-codes") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("keep") => options.color_codes = ColorCodes::Keep,
+                    Some("strip") => options.color_codes = ColorCodes::Strip,
+                    Some("invalid") => options.color_codes = ColorCodes::Invalid,
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--warn-on-ascii") => options.script_check = Some(ScriptCheck::Warn),
+                Some("--ascii-as-invalid") => options.script_check = Some(ScriptCheck::Invalid),
+                Some("--keep-raw-invalid") => options.keep_raw_invalid = true,
+                Some("--name-selectors") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(value) if value.split(',').all(|class| !class.is_empty()) => {
+                        options.name_selectors = value.split(',').map(String::from).collect();
+                    }
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--on-invalid") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("comment") => options.on_invalid = OnInvalid::Comment,
+                    Some("skip") => options.on_invalid = OnInvalid::Skip,
+                    Some("keep") => options.on_invalid = OnInvalid::Keep,
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--no-comment-invalid") => options.on_invalid = OnInvalid::Skip,
+                Some("--annotate-ids") => options.annotate_ids = true,
+                Some("--input-order") => options.input_order = true,
+                Some("--sort-names") => options.sort_names = true,
+                Some("--min-translated-ratio") => {
+                    match args.next().and_then(|v| v.to_str()?.parse().ok()) {
+                        Some(value) => options.min_translated_ratio = Some(value),
+                        None => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--max-failures-per-language") => {
+                    match args.next().and_then(|v| v.to_str()?.parse().ok()) {
+                        Some(value) if value > 0 => options.max_failures_per_language = Some(value),
+                        _ => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--max-retries") => match args.next().and_then(|v| v.to_str()?.parse().ok()) {
+                    Some(value) => options.max_retries = Some(value),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--max-inflight") => {
+                    match args.next().and_then(|v| v.to_str()?.parse().ok()) {
+                        Some(value) if value > 0 => options.max_inflight = Some(value),
+                        _ => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--requests-per-second") => {
+                    match args.next().and_then(|v| v.to_str()?.parse().ok()) {
+                        Some(value) if value > 0.0 => options.requests_per_second = Some(value),
+                        _ => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--retry-on-empty") => options.retry_on_empty = true,
+                Some("--no-fsync") => options.no_fsync = true,
+                Some("--backup") => options.backup = true,
+                Some("--dry-run") => options.dry_run = true,
+                Some("--invalid-prefix") => match args.next() {
+                    Some(value) if value.to_string_lossy().starts_with("--") => {
+                        options.invalid_prefix = Some(value.to_string_lossy().into_owned())
+                    }
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--indent") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("tab") => options.indent = Some(String::from("\t")),
+                    Some(value) => match value.parse() {
+                        Ok(width) if width > 0 => options.indent = Some(" ".repeat(width)),
+                        _ => print_usage_and_exit(&program_name),
+                    },
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--har") => match args.next() {
+                    Some(value) => options.har = Some(PathBuf::from(value)),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--jsonl") => options.jsonl = true,
+                Some("--report") => match args.next() {
+                    Some(value) => report_path = Some(PathBuf::from(value)),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--game-version") => {
+                    match args
+                        .next()
+                        .and_then(|v| GameVersion::from_name(v.to_str()?))
+                    {
+                        Some(version) => options.game_version = Some(version),
+                        None => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--schedule") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("by-language") => options.schedule = Schedule::ByLanguage,
+                    Some("by-npc") => options.schedule = Schedule::ByNpc,
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--output-format") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("lua") => options.output_format = OutputKind::Lua,
+                    Some("json") => options.output_format = OutputKind::Json,
+                    Some("toml") => options.output_format = OutputKind::Toml,
+                    _ => print_usage_and_exit(&program_name),
+                },
+                Some("--max-runtime") => {
+                    match args
+                        .next()
+                        .as_ref()
+                        .and_then(|v| parse_duration(v.to_str()?))
+                    {
+                        Some(value) => options.max_runtime = Some(value),
+                        None => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--timeouts") => {
+                    let timeouts = args
+                        .next()
+                        .as_ref()
+                        .and_then(|v| v.to_str()?.split(',').map(parse_duration).collect());
+                    match timeouts {
+                        Some(timeouts) => options.timeouts = timeouts,
+                        None => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--cache-ttl") => {
+                    match args
+                        .next()
+                        .as_ref()
+                        .and_then(|v| parse_duration(v.to_str()?))
+                    {
+                        Some(value) => options.cache_ttl = Some(value),
+                        None => print_usage_and_exit(&program_name),
+                    }
+                }
+                Some("--print-config") => print_config = true,
+                Some("--csv-dir") => match args.next() {
+                    Some(value) => csv_dir = Some(PathBuf::from(value)),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--only-invalid") => options.only_invalid = true,
+                Some("--log-file") => match args.next() {
+                    Some(value) => localize_npc_names::log_to_file(&value).map_err(|e| {
+                        format!("Couldn't open \"{}\": {}", value.to_string_lossy(), e)
+                    })?,
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--quiet") => options.quiet = true,
+                Some("--dedupe-across-languages") => options.dedupe_across_languages = true,
+                Some("--prefer-existing") => options.prefer_existing = true,
+                Some("--append-only") => options.append_only = true,
+                Some("--coverage-comment") => options.coverage_comment = true,
+                Some("--preamble") => match args.next() {
+                    Some(value) => {
+                        options.preamble = Some(fs::read_to_string(&value).map_err(|e| {
+                            format!("Couldn't read \"{}\": {}", value.to_string_lossy(), e)
+                        })?)
+                    }
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--epilogue") => match args.next() {
+                    Some(value) => {
+                        options.epilogue = Some(fs::read_to_string(&value).map_err(|e| {
+                            format!("Couldn't read \"{}\": {}", value.to_string_lossy(), e)
+                        })?)
+                    }
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--skip-unchanged") => skip_unchanged = true,
+                Some("--require-clean-git") => require_clean_git = true,
+                Some("--key-by-id") => key_by_id = true,
+                Some("--output-to-staging") => match args.next() {
+                    Some(value) => options.staging_dir = Some(PathBuf::from(value)),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--clipboard") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(value) => match resolve_language(value) {
+                        Some(code) => clipboard = Some(code),
+                        None => {
+                            eprintln!("Unknown language \"{value}\" in --clipboard");
+                            std::process::exit(1);
+                        }
+                    },
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--language-order") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(value) => {
+                        for language in value.split(',') {
+                            match resolve_language(language) {
+                                Some(code) => options.language_order.push(code.to_string()),
+                                None => {
+                                    eprintln!(
+                                        "Unknown language \"{language}\" in --language-order"
+                                    );
+                                    std::process::exit(1);
+                                }
+                            }
+                        }
+                    }
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--locales") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(value) => {
+                        let codes: Vec<_> = value.split(',').collect();
+                        if let Err(e) = Localizer::check_locales(&codes) {
+                            eprintln!("{e} (in --locales)");
+                            std::process::exit(1);
+                        }
+                        options.locales.extend(codes.into_iter().map(String::from));
+                    }
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--only") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some(value) => {
+                        for code in value.split(',') {
+                            if !Localizer::is_known_locale(code) {
+                                eprintln!("Unknown locale code \"{code}\" in --only");
+                                std::process::exit(1);
+                            }
+                            only.push(code.to_string());
+                        }
+                    }
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--snapshot") => match args.next() {
+                    Some(value) => snapshot_path = Some(PathBuf::from(value)),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--verify-snapshot") => match args.next() {
+                    Some(value) => verify_snapshot_path = Some(PathBuf::from(value)),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--emit-index") => match args.next() {
+                    Some(value) => index_path = Some(PathBuf::from(value)),
+                    None => print_usage_and_exit(&program_name),
+                },
+                Some("--index-format") => match args.next().as_ref().and_then(|v| v.to_str()) {
+                    Some("list") => index_format = IndexFormat::List,
+                    Some("xml") => index_format = IndexFormat::Xml,
+                    Some("toc") => index_format = IndexFormat::Toc,
+                    _ => print_usage_and_exit(&program_name),
+                },
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter().peekable();
+        let command = match positional.peek().and_then(|v| v.to_str()) {
+            Some("fetch") => Command::Fetch,
+            Some("apply") => Command::Apply,
+            Some("format") => Command::Format,
+            Some("names") => Command::Names,
+            Some("merge") => Command::Merge,
+            _ => Command::Run,
+        };
+        if !matches!(command, Command::Run) {
+            positional.next();
+        }
+
+        if let Command::Format = command {
+            let paths: Vec<_> = positional.map(PathBuf::from).collect();
+            if paths.is_empty() {
+                print_usage_and_exit(&program_name);
+            }
+            return format_files(&paths, &options);
+        }
+
+        if let Command::Merge = command {
+            let paths: Vec<_> = positional.map(PathBuf::from).collect();
+            if paths.len() < 2 {
+                print_usage_and_exit(&program_name);
+            }
+            return merge_inputs(&paths);
+        }
+
+        if let Command::Names = command {
+            let ids: Option<Vec<i64>> = positional
+                .map(|v| v.to_str().and_then(|v| v.parse().ok()))
+                .collect();
+            match ids {
+                Some(ids) if !ids.is_empty() => {
+                    return print_names(&ids, &only, csv_dir.as_deref(), &options)
+                }
+                _ => print_usage_and_exit(&program_name),
+            }
+        }
+
         match (
-            args.next(),
-            args.next(),
-            args.next()
+            positional.next(),
+            positional.next(),
+            positional
+                .next()
                 .map(|value| value.to_string_lossy().into_owned()),
         ) {
-            (Some(yaml_path), Some(output_dir), module_name) => (
-                PathBuf::from(yaml_path),
-                PathBuf::from(output_dir),
-                module_name,
-            ),
-            (_, _, _) => {
-                eprintln!(
-                    "Usage: {} <YAML FILE> <OUTPUT DIR> [MODULE NAME]",
-                    program_name.to_string_lossy()
-                );
-                std::process::exit(1);
+            (Some(input_path), Some(output_path), module_name) => {
+                let input_path = PathBuf::from(input_path);
+                // MODULE NAME takes precedence over `--module-name-for`.
+                let module_name =
+                    module_name.or_else(|| module_name_for(&module_names, &input_path));
+                (command, input_path, PathBuf::from(output_path), module_name)
             }
+            (_, _, _) => print_usage_and_exit(&program_name),
         }
     };
 
-    let force_all = matches!(env::var_os("FORCE_ALL"), Some(ref v) if v == "1");
-    let mut input_file = BufReader::new(File::open(&yaml_path)?);
-    let (ids_map, module_name) = match serde_yaml::from_reader::<_, InputFile>(&mut input_file) {
-        Ok(input) => {
-            let module_name = match (module_name, input.module_name) {
-                (Some(inner), _) => inner,
-                (_, Some(inner)) => inner,
-                _ => {
-                    eprintln!(
-                        "WARNING: module_name is missing, falling back to using the file's name"
+    options.force_all = matches!(env::var_os("FORCE_ALL"), Some(ref v) if v == "1");
+
+    let contents = fs::read_to_string(&input_path)?;
+    if is_effectively_empty(&contents) {
+        log!(
+            "\"{}\" is empty, there's nothing to do.",
+            input_path.display()
+        );
+        return Ok(());
+    }
+
+    let dry_run = options.dry_run;
+    let jsonl = options.jsonl;
+    let extension = options.output_format.extension();
+    // The files a run leaves behind, snapshots are taken of them.
+    let written_dir = options.staging_dir.clone().unwrap_or(output_path.clone());
+    // Whether the files in OUTPUT DIR are going to be overwritten.
+    let writes_output = !dry_run && options.staging_dir.is_none();
+    // Ctrl-C stops fetching, but whatever was fetched by then still gets written.
+    if let Err(e) = install_interrupt_handler() {
+        log!("Failed to install the Ctrl-C handler: {}", e);
+    }
+    let report = match command {
+        Command::Run => {
+            let modules = read_input(&input_path, &contents, module_name, &mut options.kinds)?;
+            if print_config {
+                return print_effective_config(&modules[0].1, &options);
+            }
+            if skip_unchanged && !options.force_all {
+                let manifest = read_manifest(&output_path);
+                if manifest
+                    .inputs
+                    .get(&manifest_key(&input_path))
+                    .is_some_and(|entry| entry.hash == content_hash(&contents))
+                {
+                    log!(
+                        "\"{}\" hasn't changed since the last successful run, skipping.",
+                        input_path.display()
                     );
-                    format!(
-                        "{} Trash",
-                        &yaml_path.file_stem().unwrap().to_string_lossy()
-                    )
+                    return Ok(());
                 }
+            }
+            fs::create_dir_all(&output_path)?;
+            if let Some(staging_dir) = options.staging_dir.as_ref().filter(|_| !dry_run) {
+                fs::create_dir_all(staging_dir)?;
+            }
+            if require_clean_git && writes_output {
+                ensure_clean_git(&output_path)?;
+            }
+            let source: Box<dyn NameSource> = match &csv_dir {
+                Some(csv_dir) => Box::new(CsvDump::open(csv_dir)?),
+                None => Box::new(Wowhead::from_options(&options)?),
             };
-            (input.npcs, module_name)
+
+            let module_names: Vec<_> = modules.iter().map(|module| module.1.clone()).collect();
+            let mut report = RunReport::default();
+            for (ids_map, module_name) in modules {
+                report.merge(Localizer::run_with_source(
+                    source.as_ref(),
+                    ids_map,
+                    &module_name,
+                    &output_path,
+                    options.clone(),
+                )?);
+            }
+
+            let succeeded = report.failed == 0
+                && !report.timed_out
+                && !report.cancelled
+                && report.below_min_ratio.is_empty();
+            if skip_unchanged && succeeded && writes_output {
+                let mut manifest = read_manifest(&output_path);
+                let last_run = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                manifest.inputs.insert(
+                    manifest_key(&input_path),
+                    ManifestEntry {
+                        hash: content_hash(&contents),
+                        last_run,
+                    },
+                );
+                write_manifest(&output_path, &manifest)?;
+            }
+            if let Some(code) = clipboard.filter(|_| !dry_run) {
+                copy_to_clipboard(&module_names, code, &output_path, &options);
+            }
+            report
         }
-        Err(_) => {
-            input_file.seek(SeekFrom::Start(0))?;
-            let ids_map = serde_yaml::from_reader(&mut input_file)?;
-            let module_name = match module_name {
-                Some(module_name) => module_name,
-                None => format!(
-                    "{} Trash",
-                    &yaml_path.file_stem().unwrap().to_string_lossy()
-                ),
+        Command::Fetch => {
+            let mut modules = read_input(&input_path, &contents, module_name, &mut options.kinds)?;
+            if modules.len() > 1 {
+                return Err("fetch only supports inputs with a single module".into());
+            }
+            let (ids_map, module_name) = modules.remove(0);
+            if print_config {
+                return print_effective_config(&module_name, &options);
+            }
+            let ids = key_by_id.then(|| ids_map.clone());
+            let (mut translations, report) = match &csv_dir {
+                Some(csv_dir) => {
+                    let source = CsvDump::open(csv_dir)?;
+                    Localizer::fetch_with_source(&source, ids_map, &module_name, options)
+                }
+                None => Localizer::fetch(ids_map, &module_name, options)?,
             };
-            (ids_map, module_name)
+            if let Some(ids) = ids {
+                translations = translations.key_by_id(&ids);
+            }
+            let dump = if is_json(&output_path) {
+                serde_json::to_string_pretty(&translations)? + "\n"
+            } else {
+                toml::to_string(&translations)?
+            };
+            fs::write(&output_path, dump)?;
+            report
+        }
+        Command::Apply => {
+            let translations: Translations = if is_json(&input_path) {
+                serde_json::from_str(&contents)?
+            } else {
+                toml::from_str(&contents)?
+            };
+            if translations.keyed_by_id {
+                return Err("names in this file are keyed by ID, they can't be applied".into());
+            }
+            let module_name = match (module_name, &translations.module_name) {
+                (Some(inner), _) => inner,
+                (_, Some(inner)) => inner.clone(),
+                _ => {
+                    log!("WARNING: module_name is missing, falling back to using the file's name");
+                    fallback_module_name(&input_path)
+                }
+            };
+            if print_config {
+                return print_effective_config(&module_name, &options);
+            }
+            fs::create_dir_all(&output_path)?;
+            if let Some(staging_dir) = options.staging_dir.as_ref().filter(|_| !dry_run) {
+                fs::create_dir_all(staging_dir)?;
+            }
+            if require_clean_git && writes_output {
+                ensure_clean_git(&output_path)?;
+            }
+            let report =
+                Localizer::apply(translations, &module_name, &output_path, options.clone());
+            if let Some(code) = clipboard.filter(|_| !dry_run) {
+                copy_to_clipboard(&[module_name], code, &output_path, &options);
+            }
+            report
+        }
+        Command::Format | Command::Names | Command::Merge => {
+            unreachable!("handled while parsing arguments")
         }
     };
 
-    fs::create_dir_all(&output_dir)?;
-    Localizer::run(ids_map, &module_name, output_dir, force_all);
+    if let Some(index_path) = index_path {
+        if !matches!(command, Command::Fetch) && !dry_run {
+            write_index(&index_path, &output_path, index_format)?;
+        }
+    }
+
+    let mut snapshot_differs = false;
+    let wants_snapshot = snapshot_path.is_some() || verify_snapshot_path.is_some();
+    if wants_snapshot && !matches!(command, Command::Fetch) && !dry_run {
+        let snapshot = take_snapshot(&written_dir, extension)?;
+        if let Some(path) = &snapshot_path {
+            fs::write(path, toml::to_string(&snapshot)?)?;
+        }
+        if let Some(path) = &verify_snapshot_path {
+            let expected: Snapshot = toml::from_str(&fs::read_to_string(path)?)?;
+            snapshot_differs = compare_snapshots(&expected, &snapshot);
+        }
+    }
+
+    if let Some(path) = &report_path {
+        fs::write(path, serde_json::to_string_pretty(&report)? + "\n")?;
+    }
+
+    for (code, kept) in &report.kept_existing {
+        log!(
+            "{code}: kept L.{} = \"{}\", fetched \"{}\"",
+            kept.name,
+            kept.existing,
+            kept.fetched
+        );
+    }
+
+    if let Some(cache) = report.cache.filter(|cache| cache.hits + cache.misses > 0) {
+        log!(
+            "Cache: {} hit(s), {} miss(es), {:.1} MiB not downloaded again",
+            cache.hits,
+            cache.misses,
+            cache.bytes_saved as f64 / (1024.0 * 1024.0)
+        );
+        if jsonl {
+            println!("{}", serde_json::json!({ "cache": cache }));
+        }
+    }
+
+    for code in &report.aborted {
+        log!("{code}: gave up after too many failures, only the names fetched before that were written");
+    }
+
+    if !report.below_min_ratio.is_empty() {
+        for (code, ratio) in &report.below_min_ratio {
+            log!(
+                "{code}: only {:.0}% of names were fetched, the file was left untouched",
+                ratio * 100.0
+            );
+        }
+        std::process::exit(1);
+    }
+
+    if report.cancelled {
+        std::process::exit(130);
+    }
+    if report.timed_out || !report.aborted.is_empty() || snapshot_differs {
+        std::process::exit(1);
+    }
 
     Ok(())
 }
@@ -0,0 +1,284 @@
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    io::{self, ErrorKind},
+};
+
+use crate::{
+    utils::{self, Entry},
+    KeptName, Map, Options,
+};
+
+/// Which [`OutputFormat`] locale files are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    /// BigWigs locale files, `if L then ... end` blocks.
+    #[default]
+    Lua,
+    /// A JSON object per locale, names are grouped by module.
+    Json,
+    /// A TOML table per module in every locale.
+    Toml,
+}
+
+impl OutputKind {
+    /// Extension of the locale files, e.g. `lua` for `deDE.lua`.
+    pub fn extension(self) -> &'static str {
+        self.format().extension()
+    }
+
+    pub(crate) fn format(self) -> &'static dyn OutputFormat {
+        match self {
+            OutputKind::Lua => &Lua,
+            OutputKind::Json => &Dump { json: true },
+            OutputKind::Toml => &Dump { json: false },
+        }
+    }
+}
+
+/// Turns names into the contents of a locale file. Reading files, temporary files and renames
+/// are the same for every format and don't belong here.
+pub(crate) trait OutputFormat: Sync {
+    fn extension(&self) -> &'static str;
+
+    /// Removes names that are already in `contents` from `map`.
+    /// With `only_invalid`, everything but the names marked as invalid is removed instead.
+    fn discard_existing(
+        &self,
+        contents: &str,
+        header: &str,
+        map: &mut Map<String, i64>,
+        only_invalid: bool,
+    );
+
+    /// Merges `values` into `existing` (`None` if there's no file yet) and returns
+    /// the new contents, or `None` if nothing would change.
+    fn write(
+        &self,
+        existing: Option<&str>,
+        header: &str,
+        values: Map<String, Entry>,
+        order: Option<&Map<String, usize>>,
+        options: &Options,
+        kept: &mut Vec<KeptName>,
+    ) -> io::Result<Option<String>>;
+}
+
+struct Lua;
+
+impl OutputFormat for Lua {
+    fn extension(&self) -> &'static str {
+        "lua"
+    }
+
+    fn discard_existing(
+        &self,
+        contents: &str,
+        header: &str,
+        map: &mut Map<String, i64>,
+        only_invalid: bool,
+    ) {
+        let _ = utils::discard_existing(&mut contents.as_bytes(), header, map, only_invalid);
+    }
+
+    fn write(
+        &self,
+        existing: Option<&str>,
+        header: &str,
+        mut values: Map<String, Entry>,
+        order: Option<&Map<String, usize>>,
+        options: &Options,
+        kept: &mut Vec<KeptName>,
+    ) -> io::Result<Option<String>> {
+        if let Some(order) = order {
+            sort_by_order(&mut values, order);
+        } else if options.sort_names {
+            values.sort_keys();
+        }
+
+        let existing = match existing {
+            Some(existing) => existing,
+            None => {
+                let contents = utils::new_file_contents(header, &values, options);
+                let contents = utils::with_boilerplate(Cow::from(contents), options);
+                return Ok(Some(if options.coverage_comment {
+                    utils::with_coverage(&contents, options)
+                } else {
+                    contents.into_owned()
+                }));
+            }
+        };
+
+        // The comment is put back once the rest is done, with the new numbers.
+        let (uncovered, had_coverage) = if options.coverage_comment {
+            utils::strip_coverage(existing, options)
+        } else {
+            (Cow::from(existing), false)
+        };
+        // New blocks get appended, the epilogue has to stay after them.
+        let body = utils::strip_epilogue(&uncovered, options);
+        let replaced = utils::replace(body, header, values, options, kept).unwrap();
+        // Sorting would move existing lines around.
+        let sorts = (order.is_some() || options.sort_names) && !options.append_only;
+        let sorted = match utils::sort_block(&replaced, header, order) {
+            Cow::Owned(sorted) if sorts => Some(sorted),
+            _ => None,
+        };
+        let new = match sorted {
+            Some(sorted) => Cow::from(sorted),
+            None => replaced,
+        };
+        let new = utils::with_boilerplate(new, options);
+        if !options.coverage_comment {
+            return Ok((new != existing).then(|| new.into_owned()));
+        }
+
+        // Only the date would change otherwise.
+        if had_coverage && new == uncovered {
+            return Ok(None);
+        }
+        Ok(Some(utils::with_coverage(&new, options)))
+    }
+}
+
+/// Names keyed by module name and then by variable name, invalid ones are kept in brackets
+/// the same way `fetch` stores them.
+type Modules = Map<String, Map<String, String>>;
+
+/// JSON or TOML dumps of names, for tools other than BigWigs.
+struct Dump {
+    json: bool,
+}
+
+impl Dump {
+    /// Headers are `L = BigWigs:NewBossLocale("Module", "deDE")`, only the module matters here.
+    fn module_name(header: &str) -> String {
+        utils::locale_declarations(header)
+            .and_then(|declarations| declarations.first().map(|(module, _)| module.to_string()))
+            .unwrap_or_default()
+    }
+
+    fn parse(&self, contents: &str) -> io::Result<Modules> {
+        let result = if self.json {
+            serde_json::from_str(contents).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(contents).map_err(|e| e.to_string())
+        };
+
+        result.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn render(&self, modules: &Modules) -> io::Result<String> {
+        let result = if self.json {
+            serde_json::to_string_pretty(modules)
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string())
+        } else {
+            toml::to_string(modules).map_err(|e| e.to_string())
+        };
+
+        result.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+impl OutputFormat for Dump {
+    fn extension(&self) -> &'static str {
+        if self.json {
+            "json"
+        } else {
+            "toml"
+        }
+    }
+
+    fn discard_existing(
+        &self,
+        contents: &str,
+        header: &str,
+        map: &mut Map<String, i64>,
+        only_invalid: bool,
+    ) {
+        let modules = match self.parse(contents) {
+            Ok(modules) => modules,
+            Err(_) => return,
+        };
+        let names = match modules.get(&Self::module_name(header)) {
+            Some(names) => names,
+            None => {
+                if only_invalid {
+                    map.clear();
+                }
+                return;
+            }
+        };
+
+        map.retain(|name, _| match names.get(name) {
+            Some(value) => {
+                let (_, is_valid) = utils::strip_invalid_marker(value.clone());
+                only_invalid && !is_valid
+            }
+            None => !only_invalid,
+        });
+    }
+
+    fn write(
+        &self,
+        existing: Option<&str>,
+        header: &str,
+        values: Map<String, Entry>,
+        order: Option<&Map<String, usize>>,
+        options: &Options,
+        kept: &mut Vec<KeptName>,
+    ) -> io::Result<Option<String>> {
+        let mut modules = match existing {
+            Some(existing) => self.parse(existing)?,
+            None => Modules::new(),
+        };
+
+        let names = modules.entry(Self::module_name(header)).or_default();
+        for (name, entry) in values {
+            let existing = names
+                .get(&name)
+                .map(|value| utils::strip_invalid_marker(value.clone()));
+            match existing {
+                // Same as with Lua files, invalid names never replace anything.
+                Some(_) if !entry.is_valid || options.append_only => continue,
+                Some((existing, true))
+                    if options.prefer_existing && existing != entry.translation =>
+                {
+                    kept.push(KeptName {
+                        name,
+                        existing,
+                        fetched: entry.translation,
+                    });
+                    continue;
+                }
+                _ => (),
+            }
+
+            let value = if entry.is_valid {
+                entry.translation
+            } else {
+                format!("[{}]", entry.translation)
+            };
+            names.insert(name, value);
+        }
+        if let Some(order) = order.filter(|_| !options.append_only) {
+            sort_by_order(names, order);
+        } else if options.sort_names && !options.append_only {
+            names.sort_keys();
+        }
+
+        let new = self.render(&modules)?;
+        Ok((existing != Some(new.as_str())).then_some(new))
+    }
+}
+
+/// Variables missing from `order` go after the known ones.
+fn sort_by_order<V>(values: &mut Map<String, V>, order: &Map<String, usize>) {
+    values.sort_by(|a, _, b, _| {
+        let a = order.get(a).unwrap_or(&usize::MAX);
+        let b = order.get(b).unwrap_or(&usize::MAX);
+        a.cmp(b)
+    });
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{utils::Entry, Map};
+
+const FILE_NAME: &str = ".localize_npc_names.resume";
+
+/// How often names fetched since the last flush are written to the resume file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+struct Fetched {
+    translation: String,
+    valid: bool,
+    id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw: Option<String>,
+}
+
+/// Fetched names keyed by module name, locale code and variable name.
+type Names = Map<String, Map<String, Map<String, Fetched>>>;
+
+struct State {
+    names: Names,
+    /// Whether there's anything that isn't in the file yet.
+    dirty: bool,
+    flushed: Instant,
+}
+
+/// Names fetched by a run that didn't get to write them, so that the next run doesn't have
+/// to fetch them again. Kept in the output directory next to the locale files, a locale is
+/// dropped from it as soon as its file is written and the file is gone once they all are.
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    module_name: String,
+    state: Mutex<State>,
+}
+
+impl Checkpoint {
+    /// Loads what an interrupted run left behind for `module_name`, unless `discard` is set
+    /// (e.g. everything is fetched again anyway), in which case it's forgotten.
+    pub(crate) fn open(output_dir: &Path, module_name: &str, discard: bool) -> Self {
+        let path = output_dir.join(FILE_NAME);
+        let mut names: Names = File::open(&path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default();
+        let dirty = discard && names.shift_remove(module_name).is_some();
+
+        Self {
+            path,
+            module_name: module_name.to_string(),
+            state: Mutex::new(State {
+                names,
+                dirty,
+                flushed: Instant::now(),
+            }),
+        }
+    }
+
+    /// The names of a locale that were fetched, as long as their IDs haven't changed since.
+    pub(crate) fn resumed(&self, code: &str, ids_map: &Map<String, i64>) -> Map<String, Entry> {
+        let state = self.state.lock().unwrap();
+        let names = match state
+            .names
+            .get(&self.module_name)
+            .and_then(|locales| locales.get(code))
+        {
+            Some(names) => names,
+            None => return Map::new(),
+        };
+
+        names
+            .iter()
+            .filter(|(name, fetched)| {
+                fetched.id.is_some() && ids_map.get(*name) == fetched.id.as_ref()
+            })
+            .map(|(name, fetched)| {
+                let entry = Entry {
+                    translation: fetched.translation.clone(),
+                    is_valid: fetched.valid,
+                    id: fetched.id,
+                    raw: fetched.raw.clone(),
+                };
+                (name.clone(), entry)
+            })
+            .collect()
+    }
+
+    /// Remembers a fetched name, the file is written if it hasn't been for a while.
+    pub(crate) fn record(&self, code: &str, name: &str, entry: &Entry) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let fetched = Fetched {
+            translation: entry.translation.clone(),
+            valid: entry.is_valid,
+            id: entry.id,
+            raw: entry.raw.clone(),
+        };
+        state
+            .names
+            .entry(self.module_name.clone())
+            .or_default()
+            .entry(code.to_string())
+            .or_default()
+            .insert(name.to_string(), fetched);
+        state.dirty = true;
+
+        if state.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.flush(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets a locale once its file has been written.
+    pub(crate) fn finish(&self, code: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let module = match state.names.get_mut(&self.module_name) {
+            Some(module) => module,
+            None => return Ok(()),
+        };
+        if module.shift_remove(code).is_some() {
+            if module.is_empty() {
+                state.names.shift_remove(&self.module_name);
+            }
+            state.dirty = true;
+            self.flush(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Writes whatever hasn't been written yet, e.g. when the run is over.
+    pub(crate) fn write(&self) -> io::Result<()> {
+        self.flush(&mut self.state.lock().unwrap())
+    }
+
+    fn flush(&self, state: &mut State) -> io::Result<()> {
+        state.flushed = Instant::now();
+        if !state.dirty {
+            return Ok(());
+        }
+        state.dirty = false;
+
+        if state.names.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        // Replaced in one go, so that a run that's killed mid-write leaves the old one.
+        let tmp_path = self.path.with_extension("resume.tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut file, &state.names)?;
+        file.flush()?;
+        drop(file);
+
+        fs::rename(&tmp_path, &self.path)
+    }
+}
@@ -0,0 +1,827 @@
+use indexmap::IndexMap as Map;
+use isahc::{
+    config::Configurable,
+    error::ErrorKind,
+    http::{
+        header::{HeaderMap, RETRY_AFTER, SERVER},
+        StatusCode, Uri,
+    },
+    HttpClient, Request, ResponseExt,
+};
+use onig::Regex;
+use select::{
+    document::Document,
+    predicate::{Attr, Class, Name},
+};
+use serde_json::Value;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    cache::{self, Cache, CacheStats},
+    default_game_version, default_max_inflight, default_max_retries, default_requests_per_second,
+    error::{Ambiguous, Blocked, EmptyName, Error, SiteUnavailable},
+    har, EmbeddedData, EntityKind, Localizer, Options, RateLimiter, Semaphore,
+};
+
+/// Somewhere names can be looked up, in every locale.
+pub trait NameSource: Sync {
+    /// Returns the name of an entity in a locale (e.g. `deDE`).
+    /// Names that aren't supposed to be used are wrapped in brackets.
+    fn fetch(&self, kind: EntityKind, id: i64, locale: &str) -> Result<String, Error>;
+
+    /// The ID a previous [`NameSource::fetch`] actually ended up at, if the source knows it
+    /// (e.g. after following a redirect).
+    fn canonical_id(&self, _kind: EntityKind, _id: i64, _locale: &str) -> Option<i64> {
+        None
+    }
+
+    /// Whether the source has names for a locale at all, the ones it doesn't are skipped.
+    fn supports(&self, _locale: &str) -> bool {
+        true
+    }
+
+    /// How much the source's cache has been used so far, if it has one.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
+
+const DEFAULT_NAME_SELECTOR: &str = "heading-size-1";
+/// How many times a request waits out a 429 before it fails.
+const RATE_LIMIT_WAITS: usize = 5;
+/// Wait after a 429 that doesn't say how long to wait.
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(30);
+/// Longest wait after a 429, whatever `Retry-After` says.
+const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+/// Wait before the first retry of a failed request, doubled for every next one.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Scrapes names from Wowhead pages. The HTTP Archive, if one was requested, and the cache are
+/// written on drop, so a single instance can be shared by several runs.
+pub struct Wowhead {
+    client: HttpClient,
+    subdomains: Map<&'static str, String>,
+    base_url: String,
+    har: Option<(PathBuf, har::Recorder)>,
+    /// IDs taken from the effective URIs, keyed by the requested kind, ID and locale.
+    canonical_ids: Mutex<Map<(EntityKind, i64, String), i64>>,
+    /// Names that make the page's canonical link be followed, see [`Options::follow_canonical`].
+    generic_name: Option<Regex>,
+    embedded_data: Option<EmbeddedData>,
+    /// See [`Options::name_selectors`], never empty.
+    name_selectors: Vec<String>,
+    /// See [`Options::timeouts`].
+    timeouts: Vec<Duration>,
+    /// See [`Options::max_retries`].
+    max_retries: usize,
+    /// See [`Options::requests_per_second`].
+    rate_limiter: RateLimiter,
+    /// See [`Options::max_inflight`].
+    inflight: Semaphore,
+    /// Names fetched by earlier runs, written back on drop.
+    cache: Option<Cache>,
+    /// Appended to the URLs names are cached under, see [`cache_variant`].
+    cache_variant: String,
+}
+
+/// Tells apart names taken from the same page in different ways: empty with the default
+/// options, so that they share the names cached by earlier versions, otherwise a fragment
+/// listing the options that change what's taken from a page.
+fn cache_variant(options: &Options) -> String {
+    let mut parts = Vec::new();
+    if !options.name_selectors.is_empty() {
+        parts.push(format!("selectors={}", options.name_selectors.join("|")));
+    }
+    match options.embedded_data {
+        Some(EmbeddedData::First) => parts.push(String::from("embedded=first")),
+        Some(EmbeddedData::Fallback) => parts.push(String::from("embedded=fallback")),
+        None => {}
+    }
+    if let Some(pattern) = &options.follow_canonical {
+        parts.push(format!("canonical={pattern}"));
+    }
+
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("#{}", parts.join("&"))
+    }
+}
+
+/// Extracts the ID from paths like `/npc=129788/sharkbait`.
+fn id_from_path(kind: EntityKind, path: &str) -> Option<i64> {
+    let prefix = format!("/{}=", kind.path());
+    let rest = &path[path.find(&prefix)? + prefix.len()..];
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+
+    rest[..end].parse().ok()
+}
+
+/// Parses `Retry-After`, either a number of seconds or an HTTP-date
+/// (`Wed, 21 Oct 2015 07:28:00 GMT`).
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = parse_http_date(value)?;
+    // A date in the past means right away.
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Parses an HTTP-date: an IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`), the only format
+/// senders are allowed to generate, or one of the obsolete ones recipients still have to accept,
+/// RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`) and asctime (`Sun Nov  6 08:49:37 1994`).
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let parts: Vec<_> = value.split_whitespace().skip(1).collect();
+    let (day, month, year, time) = match parts[..] {
+        [day, month, year, time, "GMT"] => (day, month, year.parse().ok()?, time),
+        [date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            let year: i64 = year.parse().ok()?;
+            // Two-digit years are taken to be 1970 to 2069.
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            (day, month, year, time)
+        }
+        [month, day, time, year] => (day, month, year.parse().ok()?, time),
+        _ => return None,
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|&name| name == month)? as i64 + 1;
+    let mut time = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    // Howard Hinnant's `days_from_civil`.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Whether a request failed in a way that may go away on its own, e.g. a reset connection.
+fn is_transient(error: &Error) -> bool {
+    if let Some(error) = error.downcast_ref::<isahc::Error>() {
+        return error.is_network() || error.is_timeout();
+    }
+    error.is::<io::Error>()
+}
+
+/// How long to wait before the `retry`-th retry: 500ms, 1s, 2s and so on, plus up to a half
+/// of that at random, so that parallel requests don't retry in lockstep.
+fn backoff(retry: usize) -> Duration {
+    let base = RETRY_DELAY * 2u32.saturating_pow(retry as u32);
+    let random = RandomState::new().build_hasher().finish();
+
+    base + base.mul_f64((random % 1000) as f64 / 2000.0)
+}
+
+/// Whether a response is a Cloudflare challenge page rather than the one that was requested.
+fn is_challenge(status: StatusCode, headers: &HeaderMap, body: &str) -> bool {
+    // Regular pages load scripts from `/cdn-cgi/challenge-platform/` too, so that's no marker.
+    const MARKERS: [&str; 3] = [
+        "cf-browser-verification",
+        "window._cf_chl_opt",
+        "<title>Just a moment...</title>",
+    ];
+
+    let from_cloudflare = headers
+        .get_all(SERVER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|server| server.eq_ignore_ascii_case("cloudflare"));
+
+    (from_cloudflare && status == StatusCode::FORBIDDEN)
+        || MARKERS.iter().any(|marker| body.contains(marker))
+}
+
+/// Whether a request failed because it took longer than its timeout.
+fn is_timeout(error: &Error) -> bool {
+    if let Some(error) = error.downcast_ref::<isahc::Error>() {
+        return error.kind() == ErrorKind::Timeout;
+    }
+    error
+        .downcast_ref::<io::Error>()
+        .is_some_and(|error| error.kind() == io::ErrorKind::TimedOut)
+}
+
+impl Wowhead {
+    /// Builds a client the same way [`Localizer::run`] does.
+    pub fn from_options(options: &Options) -> Result<Self, Error> {
+        Self::new(Localizer::default_client(options)?, options)
+    }
+
+    /// Fails if `--follow-canonical` isn't a valid pattern.
+    pub fn new(client: HttpClient, options: &Options) -> Result<Self, Error> {
+        let subdomains: Map<_, _> = Localizer::default_languages("")
+            .iter()
+            .map(|language| {
+                let subdomain = Localizer::subdomain(language, &options.locale_aliases);
+                (language.1, subdomain)
+            })
+            .filter(|(_, subdomain)| !subdomain.is_empty())
+            .collect();
+        let languages = if options.locales.is_empty() {
+            subdomains.len()
+        } else {
+            options.locales.len()
+        };
+
+        // Custom clients may send other headers, but there's no way to get them.
+        let har = options.har.clone().map(|path| {
+            let recorder = har::Recorder::new(Localizer::default_headers(options));
+            (path, recorder)
+        });
+        let generic_name = options
+            .follow_canonical
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|error| format!("invalid --follow-canonical pattern: {error}"))?;
+
+        Ok(Self {
+            client,
+            subdomains,
+            base_url: options.base_url.clone().unwrap_or_else(|| {
+                let version = options.game_version.unwrap_or_else(default_game_version);
+                String::from(version.base_url())
+            }),
+            har,
+            canonical_ids: Mutex::new(Map::new()),
+            generic_name,
+            embedded_data: options.embedded_data,
+            name_selectors: if options.name_selectors.is_empty() {
+                vec![String::from(DEFAULT_NAME_SELECTOR)]
+            } else {
+                options.name_selectors.clone()
+            },
+            timeouts: options.timeouts.clone(),
+            max_retries: options.max_retries.unwrap_or_else(default_max_retries),
+            // A mirror's pages would end up under Wowhead's URLs.
+            cache: options
+                .dial
+                .is_none()
+                .then(|| Cache::open(options.cache_ttl.unwrap_or(cache::DEFAULT_TTL)))
+                .flatten(),
+            cache_variant: cache_variant(options),
+            rate_limiter: RateLimiter::new(
+                options
+                    .requests_per_second
+                    .unwrap_or_else(default_requests_per_second),
+            ),
+            inflight: Semaphore::new(
+                options
+                    .max_inflight
+                    .unwrap_or_else(|| default_max_inflight(languages)),
+            ),
+        })
+    }
+
+    /// Fetches the given locales from these hosts (subdomains, full hosts or base URLs) too,
+    /// those with an empty host aren't fetched at all.
+    pub(crate) fn set_hosts(&mut self, hosts: &Map<&'static str, String>) {
+        for (&code, host) in hosts {
+            if host.is_empty() {
+                self.subdomains.shift_remove(code);
+            } else {
+                self.subdomains.insert(code, host.clone());
+            }
+        }
+    }
+
+    /// Requests `url`, returns the body and the ID of the page it ended up at.
+    fn get(&self, kind: EntityKind, url: &str) -> Result<(String, Option<i64>), Error> {
+        let uri: Uri = url.parse()?;
+        let host = uri.host().unwrap_or_default();
+        let mut attempt = 0;
+        let mut retries = 0;
+        let mut rate_limited = 0;
+        let (response, body, started, start, wait) = loop {
+            // Once they run out, the last one keeps being used.
+            let timeout = self.timeouts.get(attempt).or(self.timeouts.last()).copied();
+            // Every attempt counts, retries included.
+            self.rate_limiter.acquire(host);
+            let started = SystemTime::now();
+            let start = Instant::now();
+            let mut request = Request::get(url);
+            // Otherwise the client's own timeout applies.
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            // The timeout covers the whole transfer, a body that stalls times out as well.
+            // The permit is held until the body is read, not while waiting to retry.
+            let permit = self.inflight.acquire();
+            let result = (|| -> Result<_, Error> {
+                let mut response = self.client.send(request.body(())?)?;
+                let wait = start.elapsed();
+                let mut body = String::new();
+                response.body_mut().read_to_string(&mut body)?;
+                Ok((response, body, wait))
+            })();
+            drop(permit);
+
+            // Being rate limited isn't a failure, the request is made again once allowed.
+            if let Ok((response, ..)) = &result {
+                if response.status() == StatusCode::TOO_MANY_REQUESTS
+                    && rate_limited < RATE_LIMIT_WAITS
+                {
+                    let cooldown = retry_after(response.headers()).unwrap_or(RATE_LIMIT_COOLDOWN);
+                    thread::sleep(cooldown.min(RATE_LIMIT_MAX_WAIT));
+                    rate_limited += 1;
+                    continue;
+                }
+            }
+
+            // Timeouts escalate before they count as retries. Neither is logged, the progress
+            // line holds on to stderr while names are fetched.
+            let transient = match &result {
+                Err(e) if is_timeout(e) && attempt + 1 < self.timeouts.len() => {
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => is_transient(e),
+                Ok((response, ..)) => response.status().is_server_error(),
+            };
+
+            if transient && retries < self.max_retries {
+                thread::sleep(backoff(retries));
+                retries += 1;
+                continue;
+            }
+
+            let (response, body, wait) = result?;
+            break (response, body, started, start, wait);
+        };
+        let effective_id = response
+            .effective_uri()
+            .and_then(|uri| id_from_path(kind, uri.path()));
+
+        if let Some((_, har)) = &self.har {
+            har.record(har::Exchange {
+                url,
+                started,
+                wait,
+                receive: start.elapsed() - wait,
+                status: response.status(),
+                version: response.version(),
+                headers: response.headers(),
+                body: &body,
+            });
+        }
+
+        if is_challenge(response.status(), response.headers(), &body) {
+            return Err(Blocked.into());
+        }
+        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            return Err(SiteUnavailable.into());
+        }
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(format!("Still rate limited after waiting {rate_limited} times").into());
+        }
+
+        Ok((body, effective_id))
+    }
+
+    /// Finds the name of an entity page, in its heading, the other elements of
+    /// [`Options::name_selectors`] or its embedded data.
+    fn extract(&self, kind: EntityKind, document: &Document) -> Result<String, Error> {
+        if self.embedded_data == Some(EmbeddedData::First) {
+            if let Some(name) = Self::embedded_name(document) {
+                return Ok(name);
+            }
+        }
+        // Some layouts have nothing but an icon in the heading, the name is elsewhere.
+        let fallback = || {
+            self.name_selectors[1..]
+                .iter()
+                .filter_map(|class| document.find(Class(class.as_str())).next())
+                .map(|node| node.text())
+                .find(|text| !text.trim().is_empty())
+                .or_else(|| {
+                    (self.embedded_data == Some(EmbeddedData::Fallback))
+                        .then(|| Self::embedded_name(document))
+                        .flatten()
+                })
+        };
+
+        let heading = self.name_selectors[0].as_str();
+        let node = match document.find(Class(heading)).next() {
+            Some(node) => node,
+            None => {
+                let is_maintenance = document
+                    .find(Name("title"))
+                    .next()
+                    .is_some_and(|title| title.text().to_lowercase().contains("maintenance"));
+
+                return if is_maintenance {
+                    Err(SiteUnavailable.into())
+                } else if Self::is_search_page(document) {
+                    Err(format!("Not a valid {kind} ID").into())
+                } else if let Some(name) = fallback().or_else(|| Self::title_name(document)) {
+                    Ok(name)
+                } else {
+                    Err(format!("Couldn't find an element .{heading}").into())
+                };
+            }
+        };
+
+        // Check if we were redirected to the search page.
+        if let Some(parent) = node.parent().and_then(|n| n.parent()) {
+            if parent.is(Name("form")) {
+                return Err(format!("Not a valid {kind} ID").into());
+            }
+
+            for child in parent.children() {
+                if child.is(Class("database-detail-page-not-found-message")) {
+                    return Err(format!("Not a valid {kind} ID").into());
+                }
+            }
+        }
+
+        // A disambiguation page has a heading per entity or a list of them,
+        // either way there's no telling which one is meant.
+        let is_disambiguation = document.find(Class(heading)).nth(1).is_some()
+            || document
+                .find(Attr("id", format!("lv-{}s", kind.path()).as_str()))
+                .next()
+                .is_some();
+        if is_disambiguation {
+            return Err(Ambiguous.into());
+        }
+
+        let text = node.text();
+        if text.trim().is_empty() {
+            return fallback().ok_or_else(|| EmptyName.into());
+        }
+
+        Ok(text)
+    }
+
+    /// The name in the page's `<title>` (`Name - NPC - World of Warcraft`) or its `og:title`,
+    /// for pages that have no heading at all.
+    fn title_name(document: &Document) -> Option<String> {
+        let title = document.find(Name("title")).next().and_then(|title| {
+            // The middle part is the kind in the page's language, e.g. `Erfolg`.
+            let text = title.text();
+            let (rest, _) = text.trim().rsplit_once(" - World of Warcraft")?;
+            let (name, _) = rest.rsplit_once(" - ")?;
+            Some(name.to_string())
+        });
+
+        title
+            .or_else(|| {
+                document
+                    .find(Name("meta"))
+                    .find(|meta| meta.attr("property") == Some("og:title"))?
+                    .attr("content")
+                    .map(String::from)
+            })
+            .filter(|name| !name.trim().is_empty())
+    }
+
+    /// Whether an ID that doesn't exist led to the search page, judging by the page's own URL.
+    fn is_search_page(document: &Document) -> bool {
+        let og_url = document
+            .find(Name("meta"))
+            .find(|meta| meta.attr("property") == Some("og:url"))
+            .and_then(|meta| meta.attr("content")?.parse::<Uri>().ok())
+            .map(|uri| uri.path().to_string());
+
+        Self::canonical_path(document)
+            .into_iter()
+            .chain(og_url)
+            // Classic databases have it under their own path, e.g. `/classic/de/search`.
+            .any(|path| path.split('/').any(|segment| segment.starts_with("search")))
+            || document
+                .find(Class("database-detail-page-not-found-message"))
+                .next()
+                .is_some()
+    }
+
+    /// The name in a JSON-LD block (`{"@type": "Thing", "name": "..."}`) or
+    /// in the `g_pageInfo = {..., "name": "..."};` script, whichever comes first.
+    fn embedded_name(document: &Document) -> Option<String> {
+        let name_of = |value: &Value| {
+            value
+                .get("name")
+                .and_then(Value::as_str)
+                .filter(|name| !name.trim().is_empty())
+                .map(String::from)
+        };
+
+        let json_ld = document
+            .find(Attr("type", "application/ld+json"))
+            .filter_map(|script| serde_json::from_str::<Value>(&script.text()).ok())
+            .find_map(|value| match &value {
+                Value::Array(items) => items.iter().find_map(name_of),
+                value => name_of(value),
+            });
+        if json_ld.is_some() {
+            return json_ld;
+        }
+
+        document.find(Name("script")).find_map(|script| {
+            let text = script.text();
+            let rest = &text[text.find("g_pageInfo")?..];
+            let rest = rest[rest.find('=')? + 1..].trim_start();
+            // Only the object itself, whatever follows it isn't JSON.
+            let value = serde_json::Deserializer::from_str(rest)
+                .into_iter::<Value>()
+                .next()?
+                .ok()?;
+
+            name_of(&value)
+        })
+    }
+
+    /// Returns the name on the page at `url` and the ID of the page it ended up at.
+    fn fetch_page(
+        &self,
+        kind: EntityKind,
+        base_url: &str,
+        url: &str,
+    ) -> Result<(String, Option<i64>, u64), Error> {
+        let (body, mut effective_id) = self.get(kind, url)?;
+        let mut bytes = body.len() as u64;
+
+        let document = Document::from(body.as_str());
+        let mut text = self.extract(kind, &document)?;
+
+        // The page is about something generic, its canonical entry has the actual name.
+        // Only a single link is followed, the path goes to the same mirror.
+        let canonical_path = match &self.generic_name {
+            Some(generic_name) if generic_name.is_match(text.trim()) => {
+                Self::canonical_path(&document)
+            }
+            _ => None,
+        };
+        if let Some(path) = canonical_path {
+            let canonical_url = format!("{base_url}{path}");
+            if canonical_url != url {
+                let (body, id) = self.get(kind, &canonical_url)?;
+                bytes += body.len() as u64;
+                text = self.extract(kind, &Document::from(body.as_str()))?;
+                effective_id = id.or_else(|| id_from_path(kind, &path));
+            }
+        }
+
+        Ok((text, effective_id, bytes))
+    }
+
+    /// The path of the page's `<link rel="canonical">`, e.g. `/npc=129788/sharkbait`.
+    fn canonical_path(document: &Document) -> Option<String> {
+        let href = document
+            .find(Name("link"))
+            .find(|link| link.attr("rel") == Some("canonical"))?
+            .attr("href")?;
+        let uri: Uri = href.parse().ok()?;
+
+        uri.path_and_query().map(|path| path.to_string())
+    }
+}
+
+impl Drop for Wowhead {
+    fn drop(&mut self) {
+        if let Some((path, recorder)) = self.har.take() {
+            if let Err(e) = recorder.write(&path) {
+                log!("Failed to write \"{}\": {}", path.display(), e);
+            }
+        }
+        if let Some(cache) = self.cache.take() {
+            if let Err(e) = cache.write() {
+                log!("Failed to write the cache: {}", e);
+            }
+        }
+    }
+}
+
+/// Puts a locale's host into the base URL: a subdomain replaces `{subdomain}`, a full host
+/// (anything with a dot) replaces the whole host and a URL replaces the base URL altogether.
+fn locale_base_url(base_url: &str, subdomain: &str) -> String {
+    if subdomain.contains("://") {
+        return subdomain.to_string();
+    }
+    if !subdomain.contains('.') {
+        return base_url.replace("{subdomain}", subdomain);
+    }
+
+    match base_url.split_once("://") {
+        Some((scheme, rest)) => {
+            // The host takes the place of the subdomain, wherever the template has it.
+            let path = rest.find('/').map_or("", |i| &rest[i..]);
+            let path = path.replace("/{subdomain}", "");
+            format!("{scheme}://{subdomain}{path}")
+        }
+        None => format!("https://{subdomain}"),
+    }
+}
+
+impl NameSource for Wowhead {
+    fn supports(&self, locale: &str) -> bool {
+        self.subdomains.contains_key(locale)
+    }
+
+    fn fetch(&self, kind: EntityKind, id: i64, locale: &str) -> Result<String, Error> {
+        let subdomain = self
+            .subdomains
+            .get(locale)
+            .ok_or_else(|| format!("Unknown locale \"{locale}\""))?;
+        let base_url = locale_base_url(&self.base_url, subdomain);
+        let base_url = base_url.trim_end_matches('/');
+        let url = format!("{base_url}/{}={id}", kind.path());
+        let cache_key = format!("{url}{}", self.cache_variant);
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(&cache_key));
+        let (text, effective_id) = match cached {
+            Some(cached) => cached,
+            None => {
+                let (text, effective_id, bytes) = self.fetch_page(kind, base_url, &url)?;
+                if let Some(cache) = &self.cache {
+                    cache.insert(&cache_key, &text, effective_id, bytes);
+                }
+                (text, effective_id)
+            }
+        };
+
+        if let Some(effective_id) = effective_id {
+            self.canonical_ids
+                .lock()
+                .unwrap()
+                .insert((kind, id, locale.to_string()), effective_id);
+        }
+
+        Ok(text)
+    }
+
+    fn canonical_id(&self, kind: EntityKind, id: i64, locale: &str) -> Option<i64> {
+        self.canonical_ids
+            .lock()
+            .unwrap()
+            .get(&(kind, id, locale.to_string()))
+            .copied()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(Cache::stats)
+    }
+}
+
+/// Looks names up in CSV exports of client database tables, laid out as
+/// `<dir>/<locale>/creature.csv` (`ID` and `Name_lang` columns) and
+/// `<dir>/<locale>/achievement.csv` (`ID` and `Title_lang` columns).
+pub struct CsvDump {
+    names: Map<String, Map<(EntityKind, i64), String>>,
+}
+
+impl CsvDump {
+    /// Loads every table of every known locale found in `dir`, missing ones are skipped.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+
+        let mut names = Map::new();
+        for language in Localizer::default_languages("") {
+            let locale_dir = dir.join(language.1);
+            if !locale_dir.is_dir() {
+                continue;
+            }
+
+            let mut locale_names = Map::new();
+            for kind in [EntityKind::Npc, EntityKind::Achievement] {
+                let (table, column) = match kind {
+                    EntityKind::Npc => ("creature.csv", "Name_lang"),
+                    EntityKind::Achievement => ("achievement.csv", "Title_lang"),
+                };
+                let path = locale_dir.join(table);
+                if path.is_file() {
+                    Self::read_table(&path, column, kind, &mut locale_names)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                }
+            }
+            names.insert(language.1.to_string(), locale_names);
+        }
+
+        if names.is_empty() {
+            return Err(format!(
+                "\"{}\" doesn't contain any locale directories",
+                dir.display()
+            )
+            .into());
+        }
+
+        Ok(Self { names })
+    }
+
+    fn read_table(
+        path: &Path,
+        column: &str,
+        kind: EntityKind,
+        names: &mut Map<(EntityKind, i64), String>,
+    ) -> Result<(), Error> {
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers = reader.headers()?;
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| format!("Missing column \"{name}\""))
+        };
+        let (id_column, name_column) = (position("ID")?, position(column)?);
+
+        for record in reader.records() {
+            let record = record?;
+            if let (Some(id), Some(name)) = (record.get(id_column), record.get(name_column)) {
+                names.insert((kind, id.parse()?), name.to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl NameSource for CsvDump {
+    fn supports(&self, locale: &str) -> bool {
+        self.names.contains_key(locale)
+    }
+
+    fn fetch(&self, kind: EntityKind, id: i64, locale: &str) -> Result<String, Error> {
+        let names = self
+            .names
+            .get(locale)
+            .ok_or_else(|| format!("There's no dump for {locale}"))?;
+
+        match names.get(&(kind, id)) {
+            Some(name) if name.trim().is_empty() => Err(EmptyName.into()),
+            Some(name) => Ok(name.clone()),
+            None => Err(format!("Not a valid {kind} ID").into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Sun, 06 Nov 1994 08:49:37 GMT`.
+    const NOV_6_1994: Duration = Duration::from_secs(784_111_777);
+
+    #[test]
+    fn parse_http_date_accepts_every_format() {
+        let at = Some(SystemTime::UNIX_EPOCH + NOV_6_1994);
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), at);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), at);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), at);
+        assert_eq!(
+            parse_http_date("Tuesday, 29-Feb-00 12:00:00 GMT"),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(951_825_600))
+        );
+    }
+
+    #[test]
+    fn parse_http_date_rejects_other_dates() {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert_eq!(parse_http_date("1994-11-06T08:49:37Z"), None);
+        assert_eq!(parse_http_date(""), None);
+    }
+
+    #[test]
+    fn retry_after_reads_seconds_and_dates() {
+        let retry_after_of = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, value.parse().unwrap());
+            retry_after(&headers)
+        };
+
+        assert_eq!(retry_after_of("120"), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after_of(" 0 "), Some(Duration::ZERO));
+        // A date in the past means right away.
+        assert_eq!(
+            retry_after_of("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after_of("-5"), None);
+        assert_eq!(retry_after_of("soon"), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+}
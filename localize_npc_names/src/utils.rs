@@ -1,13 +1,16 @@
 use once_cell::sync::Lazy;
-use onig::{Regex, Replacer};
+use onig::{Captures, Regex, Replacer};
+use similar::TextDiff;
 
-use crate::Map;
+use crate::{FileStatus, KeptName, Map, OnInvalid, Options};
 use std::{
     borrow::Cow,
-    fs::{self, File},
-    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, ErrorKind, Write},
     path::{Path, PathBuf},
-    time::SystemTime,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
 };
 
 #[cfg(windows)]
@@ -15,6 +18,28 @@ const LINE_ENDING: &[u8] = b"\r\n";
 #[cfg(not(windows))]
 const LINE_ENDING: &[u8] = b"\n";
 
+/// The line ending most lines of `src` use, so that editing a file written on another OS
+/// doesn't mix them. Text without any line breaks gets the platform's one.
+fn line_ending(src: &str) -> &'static [u8] {
+    let lines = src.matches('\n').count();
+    let crlf = src.matches("\r\n").count();
+    if lines == 0 {
+        LINE_ENDING
+    } else if crlf * 2 > lines {
+        b"\r\n"
+    } else {
+        b"\n"
+    }
+}
+
+pub(crate) struct Entry {
+    pub(crate) translation: String,
+    pub(crate) is_valid: bool,
+    pub(crate) id: Option<i64>,
+    /// The value as it was fetched, kept for invalid names with `keep_raw_invalid`.
+    pub(crate) raw: Option<String>,
+}
+
 enum State {
     Initial,
     FoundLocale,
@@ -22,13 +47,106 @@ enum State {
     Done,
 }
 
-static LOCALE_ASSIGNMENT_REGEX: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r#"\s*(--)?\s*L\.(\w*)\s*=\s*"(.*?)(?<!\\)"(.*)"#).unwrap());
+enum Block {
+    Ours,
+    Other,
+}
+
+static LOCALE_DECLARATION_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"NewBossLocale\(\s*"(.*?)"\s*,\s*"(\w+)"\s*\)"#).unwrap());
+
+// Commented out entries may have a custom prefix, e.g. `-- TODO: verify L.foo = "Foo"`.
+// Values may be single-quoted (`L.foo = 'Foo'`) or in long brackets (`L.foo = [=[Foo]=]`), the
+// whole literal is the third group, see `assigned_value` for the value itself.
+static LOCALE_ASSIGNMENT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"\s*(--[^"]*?)?\s*L\.(\w*)\s*=\s*((["'])(.*?)(?<!\\)\4|\[(=*)\[(.*?)\]\6\])(.*)"#)
+        .unwrap()
+});
+
+/// The value of a line matched by `LOCALE_ASSIGNMENT_REGEX`, without the quotes and escapes.
+fn assigned_value<'a>(caps: &Captures<'a>) -> Cow<'a, str> {
+    match caps.at(5) {
+        Some(quoted) if quoted.contains('\\') => {
+            let mut value = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (c, chars.peek()) {
+                    ('\\', Some(&escaped @ ('"' | '\'' | '\\'))) => {
+                        value.push(escaped);
+                        chars.next();
+                    }
+                    _ => value.push(c),
+                }
+            }
+            Cow::from(value)
+        }
+        Some(quoted) => Cow::from(quoted),
+        None => Cow::from(caps.at(7).unwrap_or_default()),
+    }
+}
+
+/// A Lua string literal of `value`, in long brackets if it has a double quote in it.
+fn lua_string(value: &str) -> String {
+    if !value.contains('"') {
+        return format!("\"{value}\"");
+    }
+
+    // The closing bracket can't be in the value, a `]` at its end would close it early as well.
+    let mut level = String::new();
+    while format!("{value}]").contains(&format!("]{level}]")) {
+        level.push('=');
+    }
+    format!("[{level}[{value}]{level}]")
+}
 
 fn offset<'a>(haystack: &'a str, needle: &'a str) -> usize {
     needle.as_ptr() as usize - haystack.as_ptr() as usize
 }
 
+/// Returns `(module, locale)` pairs declared by an `L = ...` line,
+/// which may be an `or` chain, e.g. `L = A("X", "esES") or A("X", "esMX")`.
+pub(crate) fn locale_declarations(line: &str) -> Option<Vec<(&str, &str)>> {
+    let line = line.trim();
+    let rhs = line
+        .strip_prefix("local ")
+        .unwrap_or(line)
+        .trim_start()
+        .strip_prefix('L')?
+        .trim_start()
+        .strip_prefix('=')?;
+
+    Some(
+        LOCALE_DECLARATION_REGEX
+            .captures_iter(rhs)
+            .filter_map(|caps| Some((caps.at(1)?, caps.at(2)?)))
+            .collect(),
+    )
+}
+
+/// Lines between `-- @manual-start` and `-- @manual-end` are edited by hand and never touched.
+/// Returns whether a line starts or ends such a region.
+fn manual_marker(line: &str) -> Option<bool> {
+    match line.trim() {
+        "-- @manual-start" => Some(true),
+        "-- @manual-end" => Some(false),
+        _ => None,
+    }
+}
+
+/// Checks whether a line opens a locale block, and if so, whether it's the one `header` refers to.
+/// Blocks are matched by their primary locale, so the order and length of `or` chains don't matter.
+fn block_start(line: &str, header: &str) -> Option<Block> {
+    if line.contains(header) {
+        return Some(Block::Ours);
+    }
+
+    let declared = locale_declarations(line)?;
+    match locale_declarations(header).and_then(|v| v.into_iter().next()) {
+        Some(primary) if declared.contains(&primary) => Some(Block::Ours),
+        _ => Some(Block::Other),
+    }
+}
+
 pub(crate) fn replace_owning<R: Replacer>(
     source: String,
     regex: &Regex,
@@ -48,38 +166,113 @@ pub(crate) fn replace_owning<R: Replacer>(
     }
 }
 
-pub(crate) fn discard_existing(
-    file: &mut BufReader<File>,
+/// Wowhead wraps names of NPCs that aren't supposed to be used in brackets.
+pub(crate) fn strip_invalid_marker(translation: String) -> (String, bool) {
+    match translation.as_bytes() {
+        [b'[', rest @ .., b']'] => (String::from_utf8(rest.to_vec()).unwrap(), false),
+        _ => (translation, true),
+    }
+}
+
+/// Cuts a string down to at most `max_len` bytes without splitting a UTF-8 sequence,
+/// marking it with an ellipsis if anything was removed.
+pub(crate) fn truncate(mut source: String, max_len: usize) -> String {
+    if source.len() > max_len {
+        let boundary = (0..=max_len)
+            .rev()
+            .find(|&i| source.is_char_boundary(i))
+            .unwrap_or(0);
+        source.truncate(boundary);
+        source.push('…');
+    }
+
+    source
+}
+
+/// Returns the script names in `code` are written in if `name` doesn't have a single character
+/// of it. Such names are most likely untranslated English fallbacks. Latin locales aren't checked.
+pub(crate) fn missing_script(code: &str, name: &str) -> Option<&'static str> {
+    let (script, is_in_script): (_, fn(char) -> bool) = match code {
+        "ruRU" => ("Cyrillic", |c| matches!(c, '\u{0400}'..='\u{052F}')),
+        "koKR" => (
+            "Hangul",
+            |c| matches!(c, '\u{1100}'..='\u{11FF}' | '\u{3130}'..='\u{318F}' | '\u{AC00}'..='\u{D7AF}'),
+        ),
+        "zhCN" | "zhTW" => (
+            "Han",
+            |c| matches!(c, '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}'),
+        ),
+        _ => return None,
+    };
+
+    (!name.chars().any(is_in_script)).then_some(script)
+}
+
+/// Removes the `|cAARRGGBB` and `|r` escape sequences that color text in game,
+/// returns `None` if there aren't any.
+pub(crate) fn strip_color_codes(source: &str) -> Option<String> {
+    static COLOR_CODE_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"\|c[0-9A-Fa-f]{8}|\|r"#).unwrap());
+
+    COLOR_CODE_REGEX
+        .find(source)
+        .map(|_| COLOR_CODE_REGEX.replace_all(source, ""))
+}
+
+/// Removes the first of `prefixes` that the name starts with, as long as something is left.
+pub(crate) fn strip_prefix(mut source: String, prefixes: &[String]) -> String {
+    let prefix_len = prefixes
+        .iter()
+        .find(|prefix| {
+            source.starts_with(prefix.as_str()) && !source[prefix.len()..].trim().is_empty()
+        })
+        .map(|prefix| prefix.len());
+    if let Some(len) = prefix_len {
+        source.drain(..len);
+    }
+
+    source
+}
+
+/// Removes names that are already in the file from `map`.
+/// With `only_invalid`, everything but the commented out names is removed instead.
+pub(crate) fn discard_existing<R: BufRead>(
+    file: &mut R,
     header: &str,
     map: &mut Map<String, i64>,
+    only_invalid: bool,
 ) -> Result<(), io::Error> {
     let mut state = State::Initial;
+    let mut manual = false;
+    let mut invalid = Vec::new();
 
     let mut line = String::new();
     while file.read_line(&mut line)? > 0 {
         match state {
-            State::Initial => {
-                if line.trim().contains(header) {
-                    state = State::FoundLocale;
-                }
+            State::Initial if matches!(block_start(&line, header), Some(Block::Ours)) => {
+                state = State::FoundLocale;
             }
             State::FoundLocale => {
-                let line = line.trim();
-                if line == "if L then" {
+                if line.trim() == "if L then" {
                     state = State::InsideIf;
-                } else if line.starts_with("L =") && line != header {
+                } else if let Some(Block::Other) = block_start(&line, header) {
                     state = State::Initial;
                 }
             }
             State::InsideIf => {
                 if line.trim() == "end" {
                     break;
+                } else if let Some(start) = manual_marker(&line) {
+                    manual = start;
                 } else if let Some(caps) = LOCALE_ASSIGNMENT_REGEX.captures(&line) {
                     let is_comment = caps.at(1).is_some();
                     let name = caps.at(2).unwrap();
 
-                    if !is_comment {
-                        let _ = map.remove(name);
+                    if !is_comment || manual {
+                        // Names are fetched in the order they're left in.
+                        let _ = map.shift_remove(name);
+                    } else if only_invalid {
+                        invalid.push(name.to_string());
                     }
                 }
             }
@@ -88,31 +281,75 @@ pub(crate) fn discard_existing(
         line.clear();
     }
 
+    if only_invalid {
+        map.retain(|name, _| invalid.contains(name));
+    }
+
     Ok(())
 }
 
-fn replace<'a>(
+/// Leading whitespace of the first assignment in `src`, if there's one that's indented.
+fn detect_indent(src: &str) -> Option<&str> {
+    src.lines()
+        .filter(|line| LOCALE_ASSIGNMENT_REGEX.is_match(line))
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .find(|indent| !indent.is_empty())
+}
+
+/// How lines written into `src` are indented: the way its names already are, and
+/// [`Options::indent`] (a tab by default) if it has none.
+fn indent<'a>(src: &'a str, options: &'a Options) -> &'a str {
+    detect_indent(src)
+        .or(options.indent.as_deref())
+        .unwrap_or("\t")
+}
+
+fn format_entry(name: &str, entry: &Entry, indent: &str, options: &Options) -> String {
+    let prefix = match (entry.is_valid, &options.invalid_prefix) {
+        (true, _) => "",
+        (false, Some(prefix)) => prefix,
+        (false, None) => "-- ",
+    };
+    let mut line = format!(
+        "{indent}{prefix}L.{name} = {}",
+        lua_string(&entry.translation)
+    );
+    if let (true, Some(id)) = (options.annotate_ids, entry.id) {
+        line.push_str(&format!(" -- {id}"));
+    }
+    if let (false, Some(raw)) = (entry.is_valid, &entry.raw) {
+        line.push_str(&format!(" -- raw: {raw}"));
+    }
+
+    line
+}
+
+/// With `prefer_existing`, names that differ from those already in the file are left alone
+/// and pushed to `kept`.
+pub(crate) fn replace<'a>(
     src: &'a str,
     header: &str,
-    mut values: Map<String, (String, bool)>,
+    mut values: Map<String, Entry>,
+    options: &Options,
+    kept: &mut Vec<KeptName>,
 ) -> Option<Cow<'a, str>> {
     let mut state = State::Initial;
     let mut scratch: Vec<u8> = Vec::new();
     let mut copy_from = 0;
+    let mut manual = false;
+    let line_ending = line_ending(src);
+    let indent = indent(src, options);
 
     let bytes = src.as_bytes();
     for line in src.lines() {
         match state {
-            State::Initial => {
-                if line.trim().contains(header) {
-                    state = State::FoundLocale;
-                }
+            State::Initial if matches!(block_start(line, header), Some(Block::Ours)) => {
+                state = State::FoundLocale;
             }
             State::FoundLocale => {
-                let line = line.trim();
                 if line.trim() == "if L then" {
                     state = State::InsideIf;
-                } else if line.starts_with("L =") && line != header {
+                } else if let Some(Block::Other) = block_start(line, header) {
                     state = State::Initial;
                 }
             }
@@ -122,34 +359,66 @@ fn replace<'a>(
                         let offset = offset(src, line);
 
                         scratch.extend_from_slice(&bytes[copy_from..offset]);
-                        for (name, (translation, is_valid)) in &values {
+                        for (name, entry) in &values {
                             scratch.extend_from_slice(
-                                format!(
-                                    "{}L.{} = \"{}\"",
-                                    if *is_valid { "\t" } else { "\t-- " },
-                                    name,
-                                    translation
-                                )
-                                .as_bytes(),
+                                format_entry(name, entry, indent, options).as_bytes(),
                             );
-                            scratch.extend_from_slice(LINE_ENDING);
+                            scratch.extend_from_slice(line_ending);
                         }
                         copy_from = offset;
                     }
                     state = State::Done;
                     break;
+                } else if let Some(start) = manual_marker(line) {
+                    manual = start;
                 } else if let Some(caps) = LOCALE_ASSIGNMENT_REGEX.captures(line) {
                     let name = caps.at(2).unwrap();
 
-                    if let Some((translation, is_valid)) = values.remove(name) {
+                    if manual || options.append_only {
+                        values.shift_remove(name);
+                    } else if let Some(entry) = values.shift_remove(name) {
                         let is_comment = caps.at(1).is_some();
-                        let leftover = caps.at(4).unwrap();
-                        if is_valid && (is_comment || caps.at(3).unwrap() != translation) {
+                        let existing = assigned_value(&caps);
+                        if options.prefer_existing
+                            && !is_comment
+                            && entry.is_valid
+                            && existing != entry.translation
+                        {
+                            kept.push(KeptName {
+                                name: name.to_string(),
+                                existing: existing.into_owned(),
+                                fetched: entry.translation,
+                            });
+                            continue;
+                        }
+                        let leftover = caps.at(8).unwrap();
+                        // Only annotate lines without a trailing comment, so that re-running is a no-op.
+                        let annotation = match entry.id {
+                            Some(id) if options.annotate_ids && leftover.trim().is_empty() => {
+                                format!(" -- {id}")
+                            }
+                            _ => String::new(),
+                        };
+                        if entry.is_valid
+                            && (is_comment
+                                || existing != entry.translation
+                                || !annotation.is_empty())
+                        {
                             let offset = offset(src, line);
+                            let line_indent = &line[..line.len() - line.trim_start().len()];
 
                             scratch.extend_from_slice(&bytes[copy_from..offset]);
                             scratch.extend_from_slice(
-                                format!("\tL.{name} = \"{translation}\"{leftover}").as_bytes(),
+                                format!(
+                                    "{line_indent}L.{name} = {}{}",
+                                    lua_string(&entry.translation),
+                                    if annotation.is_empty() {
+                                        leftover
+                                    } else {
+                                        &annotation
+                                    }
+                                )
+                                .as_bytes(),
                             );
                             copy_from = offset + line.len();
                         }
@@ -176,146 +445,787 @@ fn replace<'a>(
                 scratch.extend_from_slice(b"local ");
             } else {
                 scratch.extend_from_slice(&bytes[copy_from..]);
-                scratch.extend_from_slice(LINE_ENDING);
+                scratch.extend_from_slice(line_ending);
             }
 
             scratch.extend_from_slice(header.as_bytes());
-            scratch.extend_from_slice(LINE_ENDING);
+            scratch.extend_from_slice(line_ending);
 
             if is_empty {
                 scratch.extend_from_slice(b"if not L then return end");
-                scratch.extend_from_slice(LINE_ENDING);
+                scratch.extend_from_slice(line_ending);
             }
 
             scratch.extend_from_slice(b"if L then");
-            scratch.extend_from_slice(LINE_ENDING);
-
-            for (name, (translation, is_valid)) in values {
-                scratch.extend_from_slice(
-                    format!(
-                        "{}L.{} = \"{}\"",
-                        if is_valid { "\t" } else { "\t-- " },
-                        name,
-                        translation
-                    )
-                    .as_bytes(),
-                );
-                scratch.extend_from_slice(LINE_ENDING);
+            scratch.extend_from_slice(line_ending);
+
+            for (name, entry) in &values {
+                scratch.extend_from_slice(format_entry(name, entry, indent, options).as_bytes());
+                scratch.extend_from_slice(line_ending);
             }
             scratch.extend_from_slice(b"end");
-            scratch.extend_from_slice(LINE_ENDING);
+            scratch.extend_from_slice(line_ending);
             String::from_utf8(scratch).map(Cow::from).ok()
         }
     }
 }
 
-pub(crate) fn write_to_dir(
-    output_dir: &Path,
-    tmp_dir: &Path,
-    language_code: &str,
-    header: &str,
-    values: Map<String, (String, bool)>,
-) -> Result<(), (PathBuf, io::Error)> {
-    let to_path = output_dir.join(format!("{language_code}.lua"));
-    match File::open(&to_path) {
-        // File exists, replace its contents if needed.
-        Ok(mut to_file) => {
-            let contents = {
-                let mut s = String::new();
-                to_file
-                    .read_to_string(&mut s)
-                    .map_err(|e| (to_path.clone(), e))?;
-                s
-            };
-
-            // If we didn't change anything, quit early.
-            if let Cow::Owned(replaced) = replace(&contents, header, values).unwrap() {
-                let unix_ts = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-
-                // Renaming a file is an atomic operation, writing to it is not.
-                // Create a temporary file and then rename it to prevent leaving an existing file in a bad state.
-                let tmp_path = tmp_dir.join(format!("{language_code}-{unix_ts}.lua.tmp"));
-                let mut tmp_file = File::create(&tmp_path).map_err(|e| (tmp_path.clone(), e))?;
-                tmp_file
-                    .write_all(replaced.as_bytes())
-                    .map_err(|e| (tmp_path.clone(), e))?;
-                tmp_file.sync_all().map_err(|e| (tmp_path.clone(), e))?;
-
-                drop(to_file);
-                drop(tmp_file);
-
-                // Fails if files belong to different filesystems
-                if fs::rename(&tmp_path, &to_path).is_err() {
-                    let copy_result = fs::copy(&tmp_path, &to_path);
-                    fs::remove_file(&tmp_path).map_err(|e| (tmp_path, e))?;
-
-                    if let Err(e) = copy_result {
-                        return Err((to_path, e));
-                    }
+/// The `if L then ... end` lines of the locale block `header` refers to, line endings included.
+pub(crate) fn extract_block(src: &str, header: &str) -> Option<String> {
+    let mut state = State::Initial;
+    let mut block = String::new();
+
+    for line in src.split_inclusive('\n') {
+        match state {
+            State::Initial if matches!(block_start(line, header), Some(Block::Ours)) => {
+                state = State::FoundLocale;
+            }
+            State::FoundLocale => {
+                if line.trim() == "if L then" {
+                    block.push_str(line);
+                    state = State::InsideIf;
+                } else if let Some(Block::Other) = block_start(line, header) {
+                    state = State::Initial;
                 }
             }
-        }
-        Err(e) => {
-            if e.kind() == ErrorKind::NotFound {
-                let mut to_file =
-                    BufWriter::new(File::create(&to_path).map_err(|e| (to_path.clone(), e))?);
-
-                to_file
-                    .write_all(b"local ")
-                    .map_err(|e| (to_path.clone(), e))?;
-                to_file
-                    .write_all(header.as_bytes())
-                    .map_err(|e| (to_path.clone(), e))?;
-                to_file
-                    .write_all(LINE_ENDING)
-                    .map_err(|e| (to_path.clone(), e))?;
-                to_file
-                    .write_all(b"if not L then return end")
-                    .map_err(|e| (to_path.clone(), e))?;
-                to_file
-                    .write_all(LINE_ENDING)
-                    .map_err(|e| (to_path.clone(), e))?;
-                to_file
-                    .write_all(b"if L then")
-                    .map_err(|e| (to_path.clone(), e))?;
-                to_file
-                    .write_all(LINE_ENDING)
-                    .map_err(|e| (to_path.clone(), e))?;
-
-                for (name, (translation, is_valid)) in values {
-                    write!(
-                        to_file,
-                        "{}L.{} = \"{}\"",
-                        if is_valid { "\t" } else { "\t-- " },
-                        name,
-                        translation
-                    )
-                    .map_err(|e| (to_path.clone(), e))?;
-                    to_file
-                        .write_all(LINE_ENDING)
-                        .map_err(|e| (to_path.clone(), e))?;
+            State::InsideIf => {
+                block.push_str(line);
+                if line.trim() == "end" {
+                    return Some(block);
                 }
+            }
+            _ => {}
+        }
+    }
 
-                to_file
-                    .write_all(b"end")
-                    .map_err(|e| (to_path.clone(), e))?;
-                to_file
-                    .write_all(LINE_ENDING)
-                    .map_err(|e| (to_path.clone(), e))?;
+    None
+}
 
-                to_file.flush().map_err(|e| (to_path.clone(), e))?;
-                if let Ok(to_file) = to_file.into_inner() {
-                    to_file.sync_all().map_err(|e| (to_path, e))?;
+/// Reorders assignments inside the locale block to follow `order`, or by variable name without
+/// one, every other line stays in place. Variables missing from `order` go after the known ones.
+pub(crate) fn sort_block<'a>(
+    src: &'a str,
+    header: &str,
+    order: Option<&Map<String, usize>>,
+) -> Cow<'a, str> {
+    let mut state = State::Initial;
+    let mut assignments = Vec::new();
+    let mut manual = false;
+
+    for line in src.lines() {
+        match state {
+            State::Initial if matches!(block_start(line, header), Some(Block::Ours)) => {
+                state = State::FoundLocale;
+            }
+            State::FoundLocale => {
+                if line.trim() == "if L then" {
+                    state = State::InsideIf;
+                } else if let Some(Block::Other) = block_start(line, header) {
+                    state = State::Initial;
                 }
-            } else {
-                // Insufficient permissions or whatever else.
-                return Err((to_path, e));
             }
+            State::InsideIf => {
+                if line.trim() == "end" {
+                    break;
+                } else if let Some(start) = manual_marker(line) {
+                    manual = start;
+                } else if manual {
+                    continue;
+                } else if let Some(caps) = LOCALE_ASSIGNMENT_REGEX.captures(line) {
+                    let name = caps.at(2).unwrap();
+                    let key = match order {
+                        Some(order) => (order.get(name).copied().unwrap_or(usize::MAX), ""),
+                        None => (0, name),
+                    };
+                    assignments.push((line, key));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut sorted = assignments.clone();
+    sorted.sort_by_key(|&(_, key)| key);
+    if sorted == assignments {
+        return Cow::from(src);
+    }
+
+    let mut result = String::with_capacity(src.len());
+    let mut copy_from = 0;
+    for ((slot, _), (line, _)) in assignments.into_iter().zip(sorted) {
+        let offset = offset(src, slot);
+        result.push_str(&src[copy_from..offset]);
+        result.push_str(line);
+        copy_from = offset + slot.len();
+    }
+    result.push_str(&src[copy_from..]);
+
+    Cow::from(result)
+}
+
+/// Temporary files of [`overwrite`] older than this were left behind by a run that crashed,
+/// a run that's still going is done with its own long before that.
+const STALE_TMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Removes the temporary files [`overwrite`] left behind in `dirs`, returns how many there were.
+/// Only files named the way it names them (e.g. `deDE-1700000000-4242-0.lua.tmp`) that haven't been
+/// touched in a while are removed, so other tools' files and concurrent runs are left alone.
+pub(crate) fn remove_stale_tmp_files(dirs: &[&Path]) -> usize {
+    static TMP_FILE_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\A[a-z]{2}[A-Z]{2}-\d+(-\d+-\d+)?\.(lua|json|toml)\.tmp\z").unwrap()
+    });
+
+    let mut removed = 0;
+    for dir in dirs {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let is_ours = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| TMP_FILE_REGEX.is_match(name));
+            let is_stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age >= STALE_TMP_AGE));
+            if is_ours && is_stale && fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+/// Copies `path` to `path.bak`, replacing an earlier backup.
+pub(crate) fn back_up(path: &Path) -> Result<(), (PathBuf, io::Error)> {
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(".bak");
+    let backup_path = PathBuf::from(backup_path);
+
+    fs::copy(path, &backup_path)
+        .map(|_| ())
+        .map_err(|e| (backup_path, e))
+}
+
+/// A name for a temporary file in `tmp_dir` that no other write (in this process or another one)
+/// uses, it keeps the extension of `to_path`.
+pub(crate) fn tmp_path(tmp_dir: &Path, to_path: &Path, tmp_prefix: &str) -> PathBuf {
+    // Tells apart the temporary files of this process, other processes have other IDs.
+    static TMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let unix_ts = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let extension = to_path.extension().unwrap_or_default().to_string_lossy();
+    let pid = process::id();
+    let counter = TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    tmp_dir.join(format!(
+        "{tmp_prefix}-{unix_ts}-{pid}-{counter}.{extension}.tmp"
+    ))
+}
+
+/// Replaces the contents of `to_path` without leaving it in a bad state if something goes wrong.
+pub(crate) fn overwrite(
+    tmp_dir: &Path,
+    to_path: &Path,
+    tmp_prefix: &str,
+    contents: &str,
+    options: &Options,
+) -> Result<(), (PathBuf, io::Error)> {
+    // Renaming a file is an atomic operation, writing to it is not.
+    // Create a temporary file and then rename it to prevent leaving an existing file in a bad state.
+    let tmp_path = tmp_path(tmp_dir, to_path, tmp_prefix);
+    // Fails instead of writing into a file that's somehow someone else's.
+    let mut tmp_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .map_err(|e| (tmp_path.clone(), e))?;
+    let written = tmp_file.write_all(contents.as_bytes()).and_then(|()| {
+        if options.no_fsync {
+            Ok(())
+        } else {
+            tmp_file.sync_all()
+        }
+    });
+    drop(tmp_file);
+    // A half-written temporary file is of no use to anyone.
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err((tmp_path, e));
+    }
+
+    // Fails if files belong to different filesystems
+    if fs::rename(&tmp_path, to_path).is_err() {
+        let copy_result = fs::copy(&tmp_path, to_path);
+        fs::remove_file(&tmp_path).map_err(|e| (tmp_path, e))?;
+
+        if let Err(e) = copy_result {
+            return Err((to_path.to_path_buf(), e));
         }
     }
 
     Ok(())
 }
+
+/// Rewrites every locale block the way the tool would: the `if not L then return end` guard after
+/// a `local L = ...` declaration, assignments indented the same way (see [`indent`]) with a single
+/// space around `=`, sorted by variable name. Other lines inside a block stay where they are.
+pub(crate) fn canonicalize<'a>(src: &'a str, options: &Options) -> Cow<'a, str> {
+    static GUARD: &str = "if not L then return end";
+
+    let indent = indent(src, options);
+    let mut lines: Vec<(Cow<str>, &str)> = Vec::new();
+    let mut assignments: Vec<(usize, &str)> = Vec::new();
+    let mut needs_guard = false;
+    let mut manual = false;
+    let mut state = State::Initial;
+
+    for raw in src.split_inclusive('\n') {
+        let line = raw.trim_end_matches(['\r', '\n']);
+        let ending = &raw[line.len()..];
+
+        let declares_locale = locale_declarations(line).is_some_and(|v| !v.is_empty());
+        match state {
+            State::Initial | State::FoundLocale if declares_locale => {
+                needs_guard = line.trim_start().starts_with("local ");
+                state = State::FoundLocale;
+            }
+            State::FoundLocale if line.trim() == GUARD => needs_guard = false,
+            State::FoundLocale if line.trim() == "if L then" => {
+                if needs_guard {
+                    let ending = if ending.is_empty() { "\n" } else { ending };
+                    lines.push((Cow::from(GUARD), ending));
+                }
+                lines.push((Cow::from("if L then"), ending));
+                state = State::InsideIf;
+                continue;
+            }
+            State::InsideIf if line.trim() == "end" => {
+                let mut sorted = assignments.clone();
+                sorted.sort_by_key(|&(_, name)| name);
+                let sorted: Vec<_> = sorted
+                    .into_iter()
+                    .map(|(i, _)| lines[i].0.clone())
+                    .collect();
+                for ((slot, _), line) in assignments.drain(..).zip(sorted) {
+                    lines[slot].0 = line;
+                }
+
+                lines.push((Cow::from("end"), ending));
+                state = State::Initial;
+                manual = false;
+                continue;
+            }
+            State::InsideIf if manual_marker(line).is_some() => {
+                manual = manual_marker(line).unwrap();
+            }
+            State::InsideIf if !manual => {
+                if let Some(caps) = LOCALE_ASSIGNMENT_REGEX.captures(line) {
+                    let name = caps.at(2).unwrap();
+                    let leftover = caps.at(8).unwrap().trim();
+                    let prefix = match caps.at(1) {
+                        Some(prefix) => format!("{} ", prefix.trim_end()),
+                        None => String::new(),
+                    };
+                    let line = format!(
+                        "{indent}{prefix}L.{name} = {}{}{leftover}",
+                        caps.at(3).unwrap(),
+                        if leftover.is_empty() { "" } else { " " },
+                    );
+                    assignments.push((lines.len(), name));
+                    lines.push((Cow::from(line), ending));
+                    continue;
+                }
+            }
+            _ => (),
+        }
+
+        lines.push((Cow::from(line), ending));
+    }
+
+    let result: String = lines
+        .into_iter()
+        .flat_map(|(line, ending)| [line, Cow::from(ending)])
+        .collect();
+    if result == src {
+        Cow::from(src)
+    } else {
+        Cow::from(result)
+    }
+}
+
+/// What happened to a locale file.
+pub(crate) struct Outcome {
+    pub(crate) status: FileStatus,
+    /// Names that were left alone because of [`Options::prefer_existing`].
+    pub(crate) kept: Vec<KeptName>,
+}
+
+/// What writing a locale file would do to it.
+pub(crate) enum Change {
+    Unchanged,
+    Updated { old: String, new: String },
+    Created(String),
+}
+
+/// Boilerplate (e.g. a license) is kept on lines of its own.
+fn ensure_line_ending(text: &str) -> Cow<'_, str> {
+    if text.ends_with('\n') {
+        Cow::from(text)
+    } else {
+        Cow::from(format!(
+            "{text}{}",
+            std::str::from_utf8(LINE_ENDING).unwrap()
+        ))
+    }
+}
+
+/// Puts [`Options::preamble`] and [`Options::epilogue`] around `contents`, unless they're
+/// already there.
+pub(crate) fn with_boilerplate<'a>(mut contents: Cow<'a, str>, options: &Options) -> Cow<'a, str> {
+    if let Some(preamble) = &options.preamble {
+        let preamble = ensure_line_ending(preamble);
+        if !contents.starts_with(preamble.as_ref()) {
+            contents = Cow::from(format!("{preamble}{contents}"));
+        }
+    }
+    if let Some(epilogue) = &options.epilogue {
+        let epilogue = ensure_line_ending(epilogue);
+        if !contents.ends_with(epilogue.as_ref()) {
+            contents.to_mut().push_str(&epilogue);
+        }
+    }
+
+    contents
+}
+
+/// Where the coverage comment goes, right after [`Options::preamble`] if there's one.
+fn coverage_offset(contents: &str, options: &Options) -> usize {
+    options
+        .preamble
+        .as_deref()
+        .map(ensure_line_ending)
+        .filter(|preamble| contents.starts_with(preamble.as_ref()))
+        .map_or(0, |preamble| preamble.len())
+}
+
+/// Removes the comment added by [`with_coverage`], returns whether there was one.
+pub(crate) fn strip_coverage<'a>(contents: &'a str, options: &Options) -> (Cow<'a, str>, bool) {
+    static COVERAGE_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"\A-- \d+/\d+ localized, \d+ missing \(as of [0-9-]+\)\r?\n"#).unwrap()
+    });
+
+    let at = coverage_offset(contents, options);
+    match COVERAGE_REGEX.find(&contents[at..]) {
+        Some((_, end)) => {
+            let stripped = format!("{}{}", &contents[..at], &contents[at + end..]);
+            (Cow::from(stripped), true)
+        }
+        None => (Cow::from(contents), false),
+    }
+}
+
+/// Puts a `-- 142/150 localized, 8 missing (as of 2024-03-01)` comment at the top, counting
+/// the names of every block. Missing ones are those commented out, names that couldn't be
+/// fetched at all aren't in the file.
+pub(crate) fn with_coverage(contents: &str, options: &Options) -> String {
+    let (mut localized, mut total) = (0, 0);
+    for line in contents.lines() {
+        if let Some(caps) = LOCALE_ASSIGNMENT_REGEX.captures(line) {
+            total += 1;
+            if caps.at(1).is_none() {
+                localized += 1;
+            }
+        }
+    }
+
+    let date = crate::har::iso_8601(SystemTime::now());
+    let at = coverage_offset(contents, options);
+    format!(
+        "{}-- {}/{} localized, {} missing (as of {}){}{}",
+        &contents[..at],
+        localized,
+        total,
+        total - localized,
+        &date[..10],
+        std::str::from_utf8(line_ending(contents)).unwrap(),
+        &contents[at..]
+    )
+}
+
+/// Everything before [`Options::epilogue`], new locale blocks go there.
+pub(crate) fn strip_epilogue<'a>(contents: &'a str, options: &Options) -> &'a str {
+    options
+        .epilogue
+        .as_deref()
+        .and_then(|epilogue| contents.strip_suffix(ensure_line_ending(epilogue).as_ref()))
+        .unwrap_or(contents)
+}
+
+pub(crate) fn new_file_contents(
+    header: &str,
+    values: &Map<String, Entry>,
+    options: &Options,
+) -> String {
+    let line_ending = std::str::from_utf8(LINE_ENDING).unwrap();
+    let indent = options.indent.as_deref().unwrap_or("\t");
+
+    let mut contents = format!("local {header}{line_ending}");
+    contents.push_str("if not L then return end");
+    contents.push_str(line_ending);
+    contents.push_str("if L then");
+    contents.push_str(line_ending);
+    for (name, entry) in values {
+        contents.push_str(&format_entry(name, entry, indent, options));
+        contents.push_str(line_ending);
+    }
+    contents.push_str("end");
+    contents.push_str(line_ending);
+
+    contents
+}
+
+fn prepare(
+    to_path: &Path,
+    header: &str,
+    values: Map<String, Entry>,
+    order: Option<&Map<String, usize>>,
+    options: &Options,
+) -> Result<(Change, Vec<KeptName>), io::Error> {
+    let existing = match fs::read_to_string(to_path) {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        // Insufficient permissions or whatever else.
+        Err(e) => return Err(e),
+    };
+
+    let values = match options.on_invalid {
+        OnInvalid::Comment => values,
+        OnInvalid::Skip => {
+            let values: Map<_, _> = values
+                .into_iter()
+                .filter(|(_, entry)| entry.is_valid)
+                .collect();
+            // Otherwise an empty block would be written.
+            if values.is_empty() {
+                return Ok((Change::Unchanged, Vec::new()));
+            }
+            values
+        }
+        OnInvalid::Keep => values
+            .into_iter()
+            .map(|(name, mut entry)| {
+                entry.is_valid = true;
+                (name, entry)
+            })
+            .collect(),
+    };
+
+    let mut kept = Vec::new();
+    let new = options.output_format.format().write(
+        existing.as_deref(),
+        header,
+        values,
+        order,
+        options,
+        &mut kept,
+    )?;
+    let change = match (new, existing) {
+        (None, _) => Change::Unchanged,
+        (Some(new), Some(old)) => Change::Updated { old, new },
+        (Some(new), None) => Change::Created(new),
+    };
+
+    Ok((change, kept))
+}
+
+/// Where a locale file goes, its extension depends on [`Options::output_format`].
+pub(crate) fn locale_path(output_dir: &Path, language_code: &str, options: &Options) -> PathBuf {
+    let extension = options.output_format.format().extension();
+    output_dir.join(format!("{language_code}.{extension}"))
+}
+
+pub(crate) fn write_to_dir(
+    output_dir: &Path,
+    tmp_dir: &Path,
+    language_code: &str,
+    header: &str,
+    values: Map<String, Entry>,
+    order: Option<&Map<String, usize>>,
+    options: &Options,
+) -> Result<Outcome, (PathBuf, io::Error)> {
+    let from_path = locale_path(output_dir, language_code, options);
+    let to_path = match &options.staging_dir {
+        Some(staging_dir) => locale_path(staging_dir, language_code, options),
+        None => from_path.clone(),
+    };
+    // A staged file already has what earlier modules changed, so it's the one to build on.
+    let from_path = if to_path.exists() {
+        &to_path
+    } else {
+        &from_path
+    };
+    let (change, kept) =
+        prepare(from_path, header, values, order, options).map_err(|e| (from_path.clone(), e))?;
+    let status = match change {
+        // If we didn't change anything, quit early.
+        Change::Unchanged => FileStatus::Unchanged,
+        Change::Updated { new, .. } => {
+            // A staged file that doesn't exist yet is created from the original, which stays.
+            if options.backup && from_path == &to_path {
+                back_up(&to_path)?;
+            }
+            overwrite(tmp_dir, &to_path, language_code, &new, options)?;
+            FileStatus::Updated
+        }
+        Change::Created(contents) => {
+            let mut to_file = File::create(&to_path).map_err(|e| (to_path.clone(), e))?;
+            to_file
+                .write_all(contents.as_bytes())
+                .map_err(|e| (to_path.clone(), e))?;
+            if !options.no_fsync {
+                to_file.sync_all().map_err(|e| (to_path, e))?;
+            }
+
+            FileStatus::Created
+        }
+    };
+
+    Ok(Outcome { status, kept })
+}
+
+/// Renders a git-style unified diff, `None` stands for a file that doesn't exist.
+pub(crate) fn unified_diff(path: &Path, old: Option<&str>, new: &str) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    let old_header = match old {
+        Some(_) => format!("a/{path}"),
+        None => String::from("/dev/null"),
+    };
+
+    TextDiff::from_lines(old.unwrap_or_default(), new)
+        .unified_diff()
+        .header(&old_header, &format!("b/{path}"))
+        .to_string()
+}
+
+/// Same as [`write_to_dir`], but returns a diff instead of writing anything.
+pub(crate) fn diff_dir(
+    output_dir: &Path,
+    language_code: &str,
+    header: &str,
+    values: Map<String, Entry>,
+    order: Option<&Map<String, usize>>,
+    options: &Options,
+) -> Result<(Outcome, Option<String>), (PathBuf, io::Error)> {
+    let to_path = locale_path(output_dir, language_code, options);
+    let (change, kept) =
+        prepare(&to_path, header, values, order, options).map_err(|e| (to_path.clone(), e))?;
+    let (status, diff) = match change {
+        Change::Unchanged => (FileStatus::Unchanged, None),
+        Change::Updated { old, new } => (
+            FileStatus::Updated,
+            Some(unified_diff(&to_path, Some(&old), &new)),
+        ),
+        Change::Created(contents) => (
+            FileStatus::Created,
+            Some(unified_diff(&to_path, None, &contents)),
+        ),
+    };
+
+    Ok((Outcome { status, kept }, diff))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locale_declarations_reads_or_chains() {
+        assert_eq!(
+            locale_declarations(
+                r#"local L = BigWigs:NewBossLocale("Foo", "esES") or BigWigs:NewBossLocale("Foo", "esMX")"#
+            ),
+            Some(vec![("Foo", "esES"), ("Foo", "esMX")])
+        );
+        assert_eq!(
+            locale_declarations(r#"L = BigWigs:NewBossLocale( "Foo" , "deDE" )"#),
+            Some(vec![("Foo", "deDE")])
+        );
+        assert_eq!(locale_declarations("L = mod:GetLocale()"), Some(vec![]));
+        assert_eq!(locale_declarations(r#"L.foo = "Foo""#), None);
+    }
+
+    #[test]
+    fn block_start_matches_by_primary_locale() {
+        let header = r#"local L = BigWigs:NewBossLocale("Foo", "esES") or BigWigs:NewBossLocale("Foo", "esMX")"#;
+
+        assert!(matches!(block_start(header, header), Some(Block::Ours)));
+        // The order and length of the chain don't matter.
+        let reordered = r#"local L = BigWigs:NewBossLocale("Foo", "esMX") or BigWigs:NewBossLocale("Foo", "esES")"#;
+        assert!(matches!(block_start(reordered, header), Some(Block::Ours)));
+        let shorter = r#"local L = BigWigs:NewBossLocale("Foo", "esES")"#;
+        assert!(matches!(block_start(shorter, header), Some(Block::Ours)));
+
+        let other_locale = r#"local L = BigWigs:NewBossLocale("Foo", "esMX")"#;
+        assert!(matches!(
+            block_start(other_locale, header),
+            Some(Block::Other)
+        ));
+        let other_module = r#"local L = BigWigs:NewBossLocale("Bar", "esES")"#;
+        assert!(matches!(
+            block_start(other_module, header),
+            Some(Block::Other)
+        ));
+        assert!(block_start("if L then", header).is_none());
+    }
+
+    #[test]
+    fn truncate_stops_at_a_char_boundary() {
+        assert_eq!(truncate(String::from("Foo"), 3), "Foo");
+        assert_eq!(truncate(String::from("Foobar"), 3), "Foo…");
+        // `ü` takes two bytes, the cut would fall in its middle.
+        assert_eq!(truncate(String::from("Müller"), 2), "M…");
+        assert_eq!(truncate(String::from("Müller"), 3), "Mü…");
+        // Every character takes three bytes.
+        assert_eq!(truncate(String::from("鲨鱼饵"), 4), "鲨…");
+        assert_eq!(truncate(String::from("鲨鱼饵"), 0), "…");
+    }
+
+    const BLOCK: &str = "local L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\nif not L then return end\nif L then\n\tL.gamma = \"Gamma\"\n\t-- @manual-start\n\tL.zeta = \"Zeta\"\n\t-- @manual-end\n\tL.alpha = \"Alpha\"\n\t-- L.beta = \"Beta\"\nend\n";
+
+    #[test]
+    fn sort_block_sorts_by_name_around_other_lines() {
+        let header = r#"local L = BigWigs:NewBossLocale("Foo", "deDE")"#;
+
+        let sorted = sort_block(BLOCK, header, None);
+        assert_eq!(
+            sorted,
+            "local L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\nif not L then return end\nif L then\n\tL.alpha = \"Alpha\"\n\t-- @manual-start\n\tL.zeta = \"Zeta\"\n\t-- @manual-end\n\t-- L.beta = \"Beta\"\n\tL.gamma = \"Gamma\"\nend\n"
+        );
+        assert!(matches!(
+            sort_block(&sorted, header, None),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn sort_block_follows_the_given_order() {
+        let header = r#"local L = BigWigs:NewBossLocale("Foo", "deDE")"#;
+        let order: Map<String, usize> = [("beta", 0), ("gamma", 1)]
+            .into_iter()
+            .map(|(name, i)| (name.to_string(), i))
+            .collect();
+
+        // `alpha` isn't in the order, it goes after the known ones.
+        assert_eq!(
+            sort_block(BLOCK, header, Some(&order)),
+            "local L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\nif not L then return end\nif L then\n\t-- L.beta = \"Beta\"\n\t-- @manual-start\n\tL.zeta = \"Zeta\"\n\t-- @manual-end\n\tL.gamma = \"Gamma\"\n\tL.alpha = \"Alpha\"\nend\n"
+        );
+        // Other blocks are left alone.
+        let other = r#"local L = BigWigs:NewBossLocale("Foo", "frFR")"#;
+        assert!(matches!(sort_block(BLOCK, other, None), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn canonicalize_adds_the_guard_and_normalizes_assignments() {
+        let options = Options::default();
+        let src = "local L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\r\nif L then\r\n    L.gamma=\"Gamma\"  -- note\r\n  L.alpha   =   'Alpha'\r\n\t--L.beta = [[Beta]]\r\nend\r\n";
+
+        let canonical = canonicalize(src, &options);
+        assert_eq!(
+            canonical,
+            "local L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\r\nif not L then return end\r\nif L then\r\n    L.alpha = 'Alpha'\r\n    -- L.beta = [[Beta]]\r\n    L.gamma = \"Gamma\" -- note\r\nend\r\n"
+        );
+        assert!(matches!(
+            canonicalize(&canonical, &options),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn canonicalize_leaves_manual_regions_and_global_declarations_alone() {
+        let options = Options::default();
+        let src = "L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\nif L then\n\t-- @manual-start\n\tL.zeta=\"Zeta\"\n\t-- @manual-end\n\tL.beta = \"Beta\"\n\tL.alpha = \"Alpha\"\nend";
+
+        // No guard without `local`, and the missing final line ending stays missing.
+        assert_eq!(
+            canonicalize(src, &options),
+            "L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\nif L then\n\t-- @manual-start\n\tL.zeta=\"Zeta\"\n\t-- @manual-end\n\tL.alpha = \"Alpha\"\n\tL.beta = \"Beta\"\nend"
+        );
+    }
+
+    #[test]
+    fn strip_color_codes_keeps_the_colored_text() {
+        assert_eq!(
+            strip_color_codes("|cFFFF0000Red|r and |cff00ff00green|r").as_deref(),
+            Some("Red and green")
+        );
+        // Not a color without all eight hex digits.
+        assert_eq!(
+            strip_color_codes("|cFF00Short|r").as_deref(),
+            Some("|cFF00Short")
+        );
+        assert_eq!(strip_color_codes("No colors | here"), None);
+    }
+
+    #[test]
+    fn coverage_comment_goes_after_the_preamble_and_strips_back_off() {
+        let options = Options {
+            preamble: Some(String::from("-- License")),
+            ..Default::default()
+        };
+        let src = "-- License\nlocal L = BigWigs:NewBossLocale(\"Foo\", \"deDE\")\nif L then\n\tL.alpha = \"Alpha\"\n\t-- L.beta = \"Beta\"\n\tL.gamma = \"Gamma\"\nend\n";
+
+        let covered = with_coverage(src, &options);
+        let comment = covered
+            .strip_prefix("-- License\n")
+            .and_then(|rest| rest.strip_suffix(&src["-- License\n".len()..]))
+            .unwrap();
+        assert!(comment.starts_with("-- 2/3 localized, 1 missing (as of "));
+        assert!(comment.ends_with(")\n"));
+
+        assert_eq!(strip_coverage(&covered, &options), (Cow::from(src), true));
+        assert_eq!(strip_coverage(src, &options), (Cow::from(src), false));
+        // Without the preamble it's only looked for at the very top.
+        assert!(!strip_coverage(&covered, &Options::default()).1);
+    }
+
+    /// The value [`assigned_value`] reads from an assignment line.
+    fn value_of(line: &str) -> String {
+        let caps = LOCALE_ASSIGNMENT_REGEX.captures(line).unwrap();
+        assigned_value(&caps).into_owned()
+    }
+
+    #[test]
+    fn assigned_value_reads_every_kind_of_literal() {
+        assert_eq!(value_of(r#"L.foo = "Foo""#), "Foo");
+        assert_eq!(value_of(r#"L.foo = "The \"Foo\"""#), r#"The "Foo""#);
+        assert_eq!(value_of(r"L.foo = 'Foo\'s Pet'"), "Foo's Pet");
+        assert_eq!(value_of(r#"L.foo = [["Foo"]]"#), r#""Foo""#);
+        // Only the bracket with as many `=` closes it, escapes mean nothing in there.
+        assert_eq!(
+            value_of(r#"L.foo = [=["Foo" [[x]] \"]=]"#),
+            r#""Foo" [[x]] \""#
+        );
+    }
+
+    #[test]
+    fn lua_string_picks_a_long_bracket_the_value_cant_close() {
+        assert_eq!(lua_string("Foo"), r#""Foo""#);
+        assert_eq!(lua_string(r#"The "Foo""#), r#"[[The "Foo"]]"#);
+        assert_eq!(lua_string(r#""Foo" [[x]]"#), r#"[=["Foo" [[x]]]=]"#);
+        assert_eq!(lua_string(r#""Foo" ]=]"#), r#"[==["Foo" ]=]]==]"#);
+        // A `]` at the end would close `[[...]]` early.
+        assert_eq!(lua_string(r#""Foo"]"#), r#"[=["Foo"]]=]"#);
+
+        for value in [r#"The "Foo""#, r#""Foo" [[x]]"#, r#""Foo"]"#] {
+            assert_eq!(value_of(&format!("L.foo = {}", lua_string(value))), value);
+        }
+    }
+}
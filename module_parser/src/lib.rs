@@ -0,0 +1,513 @@
+//! Parsing of BigWigs modules, shared by `generate_yaml_from_dir` and `generate_yaml_from_one`.
+
+use indexmap::IndexMap as Map;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::{fmt, io::BufRead};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[derive(Serialize)]
+pub struct ParseResult {
+    pub module_name: Option<String>,
+    #[serde(rename = "npcs")]
+    pub var_to_id_map: Map<String, i64>,
+    #[serde(skip)]
+    pub missing_vars: Vec<(String, String)>,
+    #[serde(skip)]
+    pub missing_ids: Vec<(i64, String)>,
+    #[serde(skip)]
+    pub fuzzy_matches: Vec<(String, String, String)>,
+    /// English names of the matched IDs.
+    #[serde(skip)]
+    pub names: Map<i64, String>,
+}
+
+/// Formats of `--by-id` output, English names keyed by ID.
+#[derive(Clone, Copy)]
+pub enum IdFormat {
+    Json,
+    Toml,
+}
+
+impl IdFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            IdFormat::Json => "json",
+            IdFormat::Toml => "toml",
+        }
+    }
+
+    pub fn render(self, parse_result: &ParseResult) -> Result<String, Error> {
+        // Neither format allows numbers as keys.
+        let names: Map<String, &str> = parse_result
+            .names
+            .iter()
+            .map(|(id, name)| (id.to_string(), name.as_str()))
+            .collect();
+
+        Ok(match self {
+            IdFormat::Json => serde_json::to_string_pretty(&names)? + "\n",
+            IdFormat::Toml => toml::to_string(&names)?,
+        })
+    }
+}
+
+impl fmt::Debug for ParseResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParseResult")
+            .field("Variable-to-ID map", &self.var_to_id_map)
+            .field("Missing variables", &self.missing_vars)
+            .field("Missing IDs", &self.missing_ids)
+            .field("Fuzzy matches", &self.fuzzy_matches)
+            .finish()
+    }
+}
+
+/// Trims, collapses whitespace and lowercases a name, so that `"the  Foo "` matches `"The Foo"`.
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[derive(Copy, Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
+enum ParseState {
+    ParsingIds,
+    ParsingVars,
+    Neither,
+}
+
+/// Records the IDs on a line of `mod:RegisterEnableMob(...)` under the names in the comment
+/// that follows them. IDs that share a line share the comment, so it only names them all if it
+/// lists as many comma-separated names (`1, 2, -- Foo, Bar`). A single name (`1, 2) -- Pack`)
+/// names the first one. Otherwise, or without a comment, they go to `unnamed` along with
+/// whatever comment there is.
+/// Returns `true` if the line closes the call, parentheses inside comments don't count.
+fn parse_id_line(
+    line: &str,
+    ids_map: &mut Map<String, i64>,
+    unnamed: &mut Vec<(i64, String)>,
+) -> bool {
+    let (code, comment) = line.split_once("--").unwrap_or((line, ""));
+    // Anything after a second `--` is an annotation, not a part of the name.
+    let comment = comment.split("--").next().unwrap_or_default().trim();
+
+    let ids: Vec<i64> = code
+        .split([',', ')'])
+        .filter_map(|id| id.trim().parse().ok())
+        .collect();
+    let names: Vec<_> = match ids.len() {
+        1 => vec![comment],
+        _ => comment.split(',').map(str::trim).collect(),
+    };
+
+    if !comment.is_empty() && names.len() == ids.len() {
+        for (name, id) in names.into_iter().zip(ids) {
+            ids_map.insert(name.to_string(), id);
+        }
+    } else if !comment.is_empty() && names.len() == 1 && ids.len() > 1 {
+        ids_map.insert(comment.to_string(), ids[0]);
+        unnamed.extend(ids[1..].iter().map(|&id| (id, comment.to_string())));
+    } else {
+        unnamed.extend(ids.into_iter().map(|id| (id, comment.to_string())));
+    }
+
+    code.contains(')')
+}
+
+/// Drops the backslashes of escaped quotes and backslashes, so that values match comments.
+fn unescape(value: &str) -> String {
+    static ESCAPE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\\(["'\\])"#).unwrap());
+
+    ESCAPE_REGEX.replace_all(value, "$1").into_owned()
+}
+
+pub fn parse(mut input: impl BufRead) -> Result<ParseResult, Error> {
+    static IDS_START: &str = "mod:RegisterEnableMob(";
+    static VARS_START: &str = "if L then";
+
+    // Either quote, escaped ones don't end the value. Only the opening long bracket is matched,
+    // the closing one needs as many `=` as it has.
+    static VAR_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"^\s*L\.(\w+)\s*=\s*(?:"((?:[^"\\]|\\.)+)"|'((?:[^'\\]|\\.)+)'|\[(=*)\[)"#)
+            .unwrap()
+    });
+    static MODULE_DECL_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"^\s*local\s*mod(?:,\s*CL)?\s*=\s*BigWigs:NewBoss\("(.*?)""#).unwrap()
+    });
+
+    let mut ids_map = Map::with_capacity(16);
+    let mut unnamed_ids = Vec::new();
+    let mut vars_map = Map::with_capacity(16);
+    let mut module_names: Vec<String> = Vec::new();
+
+    // The whole file is read, mobs may be registered in several calls.
+    let mut state = ParseState::Neither;
+
+    let mut line = String::new();
+    while input.read_line(&mut line)? > 0 {
+        match state {
+            ParseState::ParsingIds => {
+                if parse_id_line(&line, &mut ids_map, &mut unnamed_ids) {
+                    state = ParseState::Neither;
+                }
+            }
+            ParseState::ParsingVars => match VAR_REGEX.captures(&line) {
+                Some(caps) => {
+                    let value = match (caps.get(2).or(caps.get(3)), caps.get(4)) {
+                        (Some(quoted), _) => Some(unescape(quoted.as_str())),
+                        (None, Some(level)) => {
+                            let rest = &line[caps.get(0).unwrap().end()..];
+                            let end = format!("]{}]", level.as_str());
+                            rest.find(&end).map(|end| rest[..end].to_string())
+                        }
+                        (None, None) => None,
+                    };
+                    if let Some(value) = value {
+                        vars_map.insert(value, caps.get(1).unwrap().as_str().to_string());
+                    }
+                }
+                None => {
+                    if line.trim() == "end" {
+                        state = ParseState::Neither;
+                    }
+                }
+            },
+            ParseState::Neither => {
+                if let Some(rest) = line.trim_start().strip_prefix(IDS_START) {
+                    // The first ID may share a line with the opening parenthesis.
+                    if !parse_id_line(rest, &mut ids_map, &mut unnamed_ids) {
+                        state = ParseState::ParsingIds;
+                    }
+                } else if line.starts_with(VARS_START) {
+                    state = ParseState::ParsingVars;
+                } else if let Some(caps) = MODULE_DECL_REGEX.captures(&line) {
+                    module_names.push(caps[1].to_string());
+                }
+            }
+        }
+        line.clear();
+    }
+
+    // Names can't be told apart by module, and going with one of them would lose the others.
+    if module_names.len() > 1 {
+        return Err(format!(
+            "More than one module is declared: {}",
+            module_names.join(", ")
+        )
+        .into());
+    }
+    let module_name = module_names.pop();
+
+    // Names that don't match exactly get a second chance after normalization,
+    // unless the ID's comment is an exact match for another variable.
+    let mut fuzzy_comments = Map::new();
+    if vars_map.keys().any(|value| !ids_map.contains_key(value)) {
+        let mut normalized_ids = Map::with_capacity(ids_map.len());
+        for comment in ids_map
+            .keys()
+            .filter(|comment| !vars_map.contains_key(*comment))
+        {
+            normalized_ids
+                .entry(normalize_name(comment))
+                .or_insert_with(|| comment.clone());
+        }
+
+        for value in vars_map
+            .keys()
+            .filter(|value| !ids_map.contains_key(*value))
+        {
+            if let Some(comment) = normalized_ids.remove(&normalize_name(value)) {
+                fuzzy_comments.insert(value.clone(), comment);
+            }
+        }
+    }
+
+    let mut var_to_id_map = Map::with_capacity(vars_map.len());
+    let mut missing_vars = Vec::with_capacity(4);
+    let mut fuzzy_matches = Vec::new();
+    let mut names = Map::with_capacity(vars_map.len());
+
+    for (value, variable) in vars_map.into_iter() {
+        // Shifting keeps the IDs that are left over in the order they're in the file.
+        if let Some(id) = ids_map.shift_remove(&value) {
+            var_to_id_map.insert(variable, id);
+            names.insert(id, value);
+        } else if let Some(comment) = fuzzy_comments.remove(&value) {
+            let id = ids_map.shift_remove(&comment).unwrap();
+            var_to_id_map.insert(variable.clone(), id);
+            names.insert(id, value.clone());
+            fuzzy_matches.push((variable, value, comment));
+        } else {
+            missing_vars.push((variable, value));
+        }
+    }
+
+    let missing_ids: Vec<_> = ids_map
+        .into_iter()
+        .map(|(comment, id)| (id, comment))
+        .chain(unnamed_ids)
+        .collect();
+
+    Ok(ParseResult {
+        module_name,
+        var_to_id_map,
+        missing_vars,
+        missing_ids,
+        fuzzy_matches,
+        names,
+    })
+}
+
+/// Everything that was parsed, for `--inspect`.
+pub fn inspect(parse_result: &ParseResult) -> serde_json::Value {
+    serde_json::json!({
+        "module_name": parse_result.module_name,
+        "ids": parse_result.var_to_id_map.len() + parse_result.missing_ids.len(),
+        "matched": parse_result.var_to_id_map.len(),
+        "npcs": parse_result.var_to_id_map,
+        "missing_vars": parse_result
+            .missing_vars
+            .iter()
+            .map(|(variable, value)| serde_json::json!({ "variable": variable, "value": value }))
+            .collect::<Vec<_>>(),
+        "missing_ids": parse_result
+            .missing_ids
+            .iter()
+            .map(|(id, comment)| serde_json::json!({ "id": id, "comment": comment }))
+            .collect::<Vec<_>>(),
+        "fuzzy_matches": parse_result
+            .fuzzy_matches
+            .iter()
+            .map(|(variable, value, comment)| {
+                serde_json::json!({ "variable": variable, "value": value, "comment": comment })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &str = r#"local mod, CL = BigWigs:NewBoss("Foo Trash", 1)
+mod:RegisterEnableMob(
+	1, -- Alpha
+	2, -- the  beta
+	3 -- Gamma
+)
+local L = mod:GetLocale()
+if L then
+	L.alpha = "Alpha"
+	L.beta = "The Beta"
+	L.delta = "Delta"
+end
+"#;
+
+    #[test]
+    fn normalize_name_ignores_case_and_extra_whitespace() {
+        assert_eq!(normalize_name("  The \t Foo  Bar "), "the foo bar");
+        assert_eq!(normalize_name("the foo bar"), "the foo bar");
+        assert_eq!(normalize_name(""), "");
+    }
+
+    #[test]
+    fn parse_matches_exactly_then_fuzzily() {
+        let result = parse(MODULE.as_bytes()).unwrap();
+
+        assert_eq!(result.module_name.as_deref(), Some("Foo Trash"));
+        let matched: Vec<_> = result
+            .var_to_id_map
+            .iter()
+            .map(|(variable, &id)| (variable.as_str(), id))
+            .collect();
+        assert_eq!(matched, [("alpha", 1), ("beta", 2)]);
+        assert_eq!(
+            result.fuzzy_matches,
+            [(
+                String::from("beta"),
+                String::from("The Beta"),
+                String::from("the  beta")
+            )]
+        );
+        assert_eq!(result.missing_ids, [(3, String::from("Gamma"))]);
+        assert_eq!(
+            result.missing_vars,
+            [(String::from("delta"), String::from("Delta"))]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_several_modules() {
+        let src = "local mod = BigWigs:NewBoss(\"A\", 1)\nlocal mod = BigWigs:NewBoss(\"B\", 1)\n";
+        let error = parse(src.as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "More than one module is declared: A, B");
+    }
+
+    #[test]
+    fn id_formats_key_names_by_id() {
+        let result = parse(MODULE.as_bytes()).unwrap();
+
+        assert_eq!(
+            IdFormat::Json.render(&result).unwrap(),
+            "{\n  \"1\": \"Alpha\",\n  \"2\": \"The Beta\"\n}\n"
+        );
+        assert_eq!(
+            IdFormat::Toml.render(&result).unwrap(),
+            "1 = \"Alpha\"\n2 = \"The Beta\"\n"
+        );
+        assert_eq!(IdFormat::Toml.extension(), "toml");
+    }
+
+    #[test]
+    fn inspect_counts_every_id() {
+        let inspection = inspect(&parse(MODULE.as_bytes()).unwrap());
+
+        assert_eq!(inspection["ids"], 3);
+        assert_eq!(inspection["matched"], 2);
+        assert_eq!(inspection["npcs"]["beta"], 2);
+        assert_eq!(inspection["missing_ids"][0]["comment"], "Gamma");
+        assert_eq!(inspection["missing_vars"][0]["variable"], "delta");
+        assert_eq!(inspection["fuzzy_matches"][0]["comment"], "the  beta");
+    }
+
+    /// What [`parse_id_line`] made of a line.
+    struct IdLine {
+        named: Vec<(String, i64)>,
+        unnamed: Vec<(i64, String)>,
+        closes: bool,
+    }
+
+    fn id_line(line: &str) -> IdLine {
+        let mut ids_map = Map::new();
+        let mut unnamed = Vec::new();
+        let closes = parse_id_line(line, &mut ids_map, &mut unnamed);
+        IdLine {
+            named: ids_map.into_iter().collect(),
+            unnamed,
+            closes,
+        }
+    }
+
+    #[test]
+    fn parse_id_line_ignores_parentheses_in_comments() {
+        let line = id_line("\t1, -- Foo (Heroic)\n");
+        assert_eq!(line.named, [(String::from("Foo (Heroic)"), 1)]);
+        assert!(line.unnamed.is_empty());
+        assert!(!line.closes);
+
+        let line = id_line("\t2) -- Bar\n");
+        assert_eq!(line.named, [(String::from("Bar"), 2)]);
+        assert!(line.closes);
+
+        // Anything after a second `--` is an annotation.
+        let line = id_line("\t3, -- Baz -- (only on mythic)\n");
+        assert_eq!(line.named, [(String::from("Baz"), 3)]);
+
+        let line = id_line("\t4,\n");
+        assert!(line.named.is_empty());
+        assert_eq!(line.unnamed, [(4, String::new())]);
+        assert!(!line.closes);
+    }
+
+    #[test]
+    fn parse_reads_ids_on_the_opening_line() {
+        let src = "mod:RegisterEnableMob(1, -- Alpha\n\t2) -- Beta (boss)\nif L then\n\tL.alpha = \"Alpha\"\n\tL.beta = \"Beta (boss)\"\nend\n";
+        let result = parse(src.as_bytes()).unwrap();
+
+        let matched: Vec<_> = result.var_to_id_map.into_iter().collect();
+        assert_eq!(
+            matched,
+            [(String::from("alpha"), 1), (String::from("beta"), 2)]
+        );
+        assert!(result.missing_ids.is_empty());
+
+        let single_line = "mod:RegisterEnableMob(3) -- Gamma\n";
+        let result = parse(single_line.as_bytes()).unwrap();
+        assert_eq!(result.missing_ids, [(3, String::from("Gamma"))]);
+    }
+
+    #[test]
+    fn parse_id_line_names_several_ids_from_a_list() {
+        let line = id_line("\t1, 2, 3, -- Foo, Bar, Baz\n");
+        assert_eq!(
+            line.named,
+            [
+                (String::from("Foo"), 1),
+                (String::from("Bar"), 2),
+                (String::from("Baz"), 3)
+            ]
+        );
+        assert!(line.unnamed.is_empty());
+
+        // With fewer names than IDs there's no telling which is which.
+        let line = id_line("\t4, 5, 6 -- Foo, Bar\n");
+        assert!(line.named.is_empty());
+        assert_eq!(
+            line.unnamed,
+            [
+                (4, String::from("Foo, Bar")),
+                (5, String::from("Foo, Bar")),
+                (6, String::from("Foo, Bar"))
+            ]
+        );
+
+        let line = id_line("\t7, 8)\n");
+        assert!(line.named.is_empty());
+        assert_eq!(line.unnamed, [(7, String::new()), (8, String::new())]);
+        assert!(line.closes);
+    }
+
+    #[test]
+    fn parse_id_line_gives_a_single_name_to_the_first_id() {
+        let line = id_line("\t1, 2, 3) -- Pack Leader\n");
+        assert_eq!(line.named, [(String::from("Pack Leader"), 1)]);
+        assert_eq!(
+            line.unnamed,
+            [
+                (2, String::from("Pack Leader")),
+                (3, String::from("Pack Leader"))
+            ]
+        );
+        assert!(line.closes);
+    }
+
+    #[test]
+    fn unescape_drops_the_backslashes_of_quotes_and_backslashes() {
+        assert_eq!(unescape(r#"The \"Boss\""#), r#"The "Boss""#);
+        assert_eq!(unescape(r"Tak\'s Pet"), "Tak's Pet");
+        assert_eq!(unescape(r"Back\\slash"), r"Back\slash");
+        // Other escapes aren't a part of any name, they're left as they are.
+        assert_eq!(unescape(r"Line\nBreak"), r"Line\nBreak");
+    }
+
+    #[test]
+    fn parse_reads_single_quoted_values() {
+        let src = "mod:RegisterEnableMob(\n\t1, -- Tak's Pet\n\t2 -- The \"Boss\"\n)\nif L then\n\tL.pet = 'Tak\\'s Pet'\n\tL.boss = 'The \"Boss\"'\nend\n";
+        let result = parse(src.as_bytes()).unwrap();
+
+        let matched: Vec<_> = result.var_to_id_map.into_iter().collect();
+        assert_eq!(
+            matched,
+            [(String::from("pet"), 1), (String::from("boss"), 2)]
+        );
+        assert!(result.missing_vars.is_empty());
+    }
+
+    #[test]
+    fn parse_reads_values_in_long_brackets() {
+        let src = "mod:RegisterEnableMob(\n\t1, -- The \"Boss\"\n\t2 -- Foo [[x]] Bar\n)\nif L then\n\tL.boss = [[The \"Boss\"]]\n\tL.foo = [=[Foo [[x]] Bar]=]\n\tL.open = [==[Never closed]=]\nend\n";
+        let result = parse(src.as_bytes()).unwrap();
+
+        let matched: Vec<_> = result.var_to_id_map.into_iter().collect();
+        assert_eq!(
+            matched,
+            [(String::from("boss"), 1), (String::from("foo"), 2)]
+        );
+        // A value without its closing bracket isn't read at all.
+        assert!(result.missing_vars.is_empty());
+    }
+}